@@ -4,7 +4,6 @@ use hampter::{
 	auth::AuthorizedClient,
 	types::{chat::{self, MessageChunk}, error::HampterError, profile},
 };
-use tokio;
 
 #[tokio::main]
 async fn main() -> Result<(), HampterError>{
@@ -34,8 +33,8 @@ async fn main() -> Result<(), HampterError>{
 		if line_content.is_none() { break; } // check for the stream end
 		let json_str = &line_content.unwrap().unwrap();
 		let chunk = MessageChunk::from_line(json_str)?;
-		if chunk.is_some() {
-			print!("{}", chunk.unwrap().content(None));
+		if let Some(chunk) = chunk {
+			print!("{}", chunk.content(None));
 			let _ = io::stdout().flush();
 		}
 	}
@@ -1,31 +1,186 @@
-use crate::types::error::HampterError;
-use reqwest::{self, header::HeaderValue};
+use crate::transport::{HttpRequest, HttpResponse, HttpTransport, ReqwestTransport};
+use crate::types::{error::HampterError, schema, secret::Secret};
+use base64::Engine;
+use getters2::Getters;
+use reqwest::Method;
 use serde_json::json;
 
+const DEFAULT_AUTH_URL: &str = "https://auth.janitorai.com";
+
+/**
+ * Holds the authorization and header data attached to every request
+ * Generic over the transport so the HTTP backend can be swapped out
+ */
 #[allow(unused)]
-pub struct AuthorizedClient {
-	client: reqwest::Client,
+pub struct AuthorizedClient<T: HttpTransport = ReqwestTransport> {
+	transport: T,
 	user_agent: String,
-	cf_clearance: String,
-	auth_token: String,
-	refresh_token: String,
+	cf_clearance: Secret,
+	auth_token: Secret,
+	refresh_token: Secret,
 	x_app_version: String,
-	api_key: String,
+	api_key: Secret,
+	auth_url: String,
 }
 
-impl AuthorizedClient {
-	pub fn client(&self) -> &reqwest::Client {
-		&self.client
+/**
+ * The user account as returned by the auth server
+ */
+#[derive(serde::Deserialize, serde::Serialize, Getters)]
+pub struct User {
+	id: String,
+	aud: String,
+	role: String,
+	email: Option<String>,
+	created_at: chrono::DateTime<chrono::Utc>,
+	updated_at: Option<chrono::DateTime<chrono::Utc>>,
+	last_sign_in_at: Option<chrono::DateTime<chrono::Utc>>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<User, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+schema::modeled!(User, [
+	"id", "aud", "role", "email", "created_at", "updated_at", "last_sign_in_at",
+]);
+
+/**
+ * The payload of the bearer JWT
+ * Decoded without verifying the signature, only use it for informational purposes
+ */
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct Claims {
+	sub: String,
+	email: Option<String>,
+	role: Option<String>,
+	session_id: Option<String>,
+	iat: i64,
+	exp: i64,
+}
+
+impl Claims {
+	/**
+	 * The ID of the user the token was issued for
+	 */
+	pub fn user_id(&self) -> &str {
+		&self.sub
+	}
+
+	pub fn issued_at(&self) -> chrono::DateTime<chrono::Utc> {
+		chrono::DateTime::from_timestamp(self.iat, 0).unwrap_or_default()
+	}
+
+	pub fn expires_at(&self) -> chrono::DateTime<chrono::Utc> {
+		chrono::DateTime::from_timestamp(self.exp, 0).unwrap_or_default()
+	}
+
+	/**
+	 * Negative if the token already expired
+	 */
+	pub fn time_until_expiry(&self) -> chrono::TimeDelta {
+		self.expires_at() - chrono::Utc::now()
+	}
+
+	pub fn is_expired(&self) -> bool {
+		self.time_until_expiry() <= chrono::TimeDelta::zero()
+	}
+
+	fn decode(token: &str) -> Result<Claims, HampterError> {
+		let payload = token.split('.').nth(1).ok_or(HampterError::InvalidToken)?;
+		let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+			.decode(payload.trim_end_matches('='))
+			.map_err(|_| HampterError::InvalidToken)?;
+		Ok(serde_json::from_slice(&bytes)?)
+	}
+}
+
+#[derive(serde::Deserialize)]
+#[allow(unused)]
+struct TokenResponse {
+	access_token: Secret,
+	token_type: String,
+	expires_in: u32,
+	expires_at: u64,
+	refresh_token: Secret,
+}
+
+impl<T: HttpTransport> AuthorizedClient<T> {
+	pub fn transport(&self) -> &T {
+		&self.transport
+	}
+
+	/**
+	 * Swap the transport while keeping the credentials, e.g. to wrap it in a `cassette::Recorder`
+	 */
+	pub fn map_transport<U: HttpTransport>(self, f: impl FnOnce(T) -> U) -> AuthorizedClient<U> {
+		AuthorizedClient {
+			transport: f(self.transport),
+			user_agent: self.user_agent,
+			cf_clearance: self.cf_clearance,
+			auth_token: self.auth_token,
+			refresh_token: self.refresh_token,
+			x_app_version: self.x_app_version,
+			api_key: self.api_key,
+			auth_url: self.auth_url,
+		}
 	}
 	#[allow(dead_code)]
 	pub(crate) fn auth_token(&self) -> &str {
-		&self.auth_token
+		self.auth_token.expose()
+	}
+
+	/**
+	 * Decode the claims of the current bearer
+	 */
+	pub fn claims(&self) -> Result<Claims, HampterError> {
+		Claims::decode(self.auth_token.expose())
+	}
+
+	/**
+	 * Shorthand for `claims()?.time_until_expiry()`
+	 */
+	pub fn time_until_expiry(&self) -> Result<chrono::TimeDelta, HampterError> {
+		Ok(self.claims()?.time_until_expiry())
+	}
+
+	/**
+	 * Create a request carrying the cf_clearance cookie, user agent, bearer and app version
+	 */
+	pub fn request(&self, method: Method, url: impl Into<String>) -> Result<HttpRequest, HampterError> {
+		let request = HttpRequest::new(method, url)
+			.header("Cookie", &format!("cf_clearance={}", self.cf_clearance.expose()))?
+			.header("User-Agent", &self.user_agent)?
+			.header("x-app-version", &self.x_app_version)?;
+		if self.auth_token.is_empty() {
+			return Ok(request);
+		}
+		request.header("Authorization", &format!("Bearer {}", self.auth_token.expose()))
+	}
+
+	pub fn get(&self, url: impl Into<String>) -> Result<HttpRequest, HampterError> {
+		self.request(Method::GET, url)
+	}
+
+	pub fn post(&self, url: impl Into<String>) -> Result<HttpRequest, HampterError> {
+		self.request(Method::POST, url)
+	}
+
+	pub fn patch(&self, url: impl Into<String>) -> Result<HttpRequest, HampterError> {
+		self.request(Method::PATCH, url)
+	}
+
+	pub fn delete(&self, url: impl Into<String>) -> Result<HttpRequest, HampterError> {
+		self.request(Method::DELETE, url)
+	}
+
+	/**
+	 * Execute a request through the transport
+	 * Responses with an error status are turned into `HampterError::Status`
+	 */
+	pub async fn send(&self, request: HttpRequest) -> Result<HttpResponse, HampterError> {
+		self.transport.execute(request).await?.error_for_status()
 	}
 }
 
-/**
- * A basic wrapper that contains a reqwest::Client holding necessary authorization and header data
- */
 impl AuthorizedClient {
 	pub fn new(
 		user_agent: &str,
@@ -35,36 +190,87 @@ impl AuthorizedClient {
 		x_app_version: &str,
 		api_key: &str,
 	) -> Result<AuthorizedClient, HampterError> {
-		let mut request_headers: reqwest::header::HeaderMap = reqwest::header::HeaderMap::new();
-		request_headers.insert(
-			"Cookie",
-			reqwest::header::HeaderValue::from_str(&format!("cf_clearance={}", cf_clearance))?,
-		);
-		request_headers.insert(
-			"User-Agent",
-			reqwest::header::HeaderValue::from_str(user_agent)?,
-		);
-		request_headers.insert(
-			"Authorization",
-			reqwest::header::HeaderValue::from_str(&format!("Bearer {}", auth_token))?,
-		);
-		request_headers.insert(
-			"x-app-version",
-			reqwest::header::HeaderValue::from_str(x_app_version)?,
-		);
-
-		Ok(AuthorizedClient {
-			client: reqwest::ClientBuilder::new()
-				.cookie_store(true)
-				.default_headers(request_headers)
-				.build()?,
+		Ok(Self::with_transport(
+			ReqwestTransport::new()?,
+			user_agent,
+			cf_clearance,
+			auth_token,
+			refresh_token,
+			x_app_version,
+			api_key,
+		))
+	}
+
+	/**
+	 * Log in with email and password
+	 * `auth_url` defaults to JanitorAI's auth server and can point at any GoTrue compatible server
+	 */
+	pub async fn login_with_password(
+		user_agent: &str,
+		cf_clearance: &str,
+		x_app_version: &str,
+		api_key: &str,
+		email: &str,
+		password: &str,
+		auth_url: Option<&str>,
+	) -> Result<AuthorizedClient, HampterError> {
+		let mut client = Self::new(user_agent, cf_clearance, "", "", x_app_version, api_key)?;
+		if let Some(url) = auth_url {
+			client.set_auth_url(url);
+		}
+		client.sign_in(email, password).await?;
+		Ok(client)
+	}
+}
+
+impl<T: HttpTransport> AuthorizedClient<T> {
+	pub fn with_transport(
+		transport: T,
+		user_agent: &str,
+		cf_clearance: &str,
+		auth_token: &str,
+		refresh_token: &str,
+		x_app_version: &str,
+		api_key: &str,
+	) -> AuthorizedClient<T> {
+		AuthorizedClient {
+			transport,
 			user_agent: user_agent.to_string(),
-			cf_clearance: cf_clearance.to_string(),
-			auth_token: auth_token.to_string(),
-			refresh_token: refresh_token.to_string(),
+			cf_clearance: Secret::from(cf_clearance),
+			auth_token: Secret::from(auth_token),
+			refresh_token: Secret::from(refresh_token),
 			x_app_version: x_app_version.to_string(),
-			api_key: api_key.to_string(),
-		})
+			api_key: Secret::from(api_key),
+			auth_url: DEFAULT_AUTH_URL.to_string(),
+		}
+	}
+
+	/**
+	 * Use a different auth server than JanitorAI's
+	 */
+	pub fn set_auth_url(&mut self, auth_url: &str) {
+		self.auth_url = auth_url.trim_end_matches('/').to_string();
+	}
+
+	/**
+	 * Exchange email and password for a token pair
+	 * The refresh-token and bearer are modified in place
+	 */
+	pub async fn sign_in(&mut self, email: &str, password: &str) -> Result<(), HampterError> {
+		let parsed_res = self
+			.send(
+				self.post(format!("{}/auth/v1/token?grant_type=password", self.auth_url))?
+					.json(&json!({
+						"email": email,
+						"password": password
+					}))?
+					.header("apikey", self.api_key.expose())?,
+			)
+			.await?
+			.json::<TokenResponse>()
+			.await?;
+		self.apply_token(parsed_res);
+		Ok(())
 	}
 
 	/**
@@ -72,29 +278,54 @@ impl AuthorizedClient {
 	 * The refresh-token and bearer are modified in place
 	 */
 	pub async fn refresh_auth_token(&mut self) -> Result<(), HampterError> {
-		#[derive(serde::Deserialize)]
-		#[allow(unused)]
-		struct Response {
-			access_token: String,
-			token_type: String,
-			expires_in: u32,
-			expires_at: u64,
-			refresh_token: String,
-		}
-
-		let res = self
-			.client
-			.post("https://auth.janitorai.com/auth/v1/token?grant_type=refresh_token")
-			.json(&json!({
-			"refresh_token":self.refresh_token
-			}))
-			.header("apikey", HeaderValue::from_str(&self.api_key)?)
-			.send()
+		let parsed_res = self
+			.send(
+				self.post(format!(
+					"{}/auth/v1/token?grant_type=refresh_token",
+					self.auth_url
+				))?
+				.json(&json!({
+				"refresh_token":self.refresh_token.expose()
+				}))?
+				.header("apikey", self.api_key.expose())?,
+			)
+			.await?
+			.json::<TokenResponse>()
 			.await?;
+		self.apply_token(parsed_res);
+		Ok(())
+	}
 
-		let parsed_res = res.error_for_status()?.json::<Response>().await?;
-		self.refresh_token = parsed_res.refresh_token;
-		self.auth_token = parsed_res.access_token;
+	/**
+	 * Invalidate the current session on the auth server
+	 * The bearer and refresh token are cleared, later requests are sent unauthorized
+	 */
+	pub async fn logout(&mut self) -> Result<(), HampterError> {
+		self.send(
+			self.post(format!("{}/auth/v1/logout", self.auth_url))?
+				.header("apikey", self.api_key.expose())?,
+		)
+		.await?;
+		self.auth_token = Secret::default();
+		self.refresh_token = Secret::default();
 		Ok(())
 	}
+
+	/**
+	 * Request the account the bearer belongs to
+	 */
+	pub async fn get_user(&self) -> Result<User, HampterError> {
+		self.send(
+			self.get(format!("{}/auth/v1/user", self.auth_url))?
+				.header("apikey", self.api_key.expose())?,
+		)
+		.await?
+		.json::<User>()
+		.await
+	}
+
+	fn apply_token(&mut self, token: TokenResponse) {
+		self.refresh_token = token.refresh_token;
+		self.auth_token = token.access_token;
+	}
 }
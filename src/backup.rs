@@ -0,0 +1,452 @@
+/*!
+ * Account backups
+ * Chats and own characters can't be listed through the API, a backup only holds the ones named in
+ * `BackupOptions`. Everything else of the account is collected on its own.
+ * An archive is a directory of JSON files next to a `manifest.json` naming its version:
+ * `profile.json`, `personas.json`, `following_tags.json`, `favourites.json`,
+ * `chats/<id>.json`, `characters/<id>.json` and the images in `avatars/`.
+ * LLM keys in the profile config are `Secret`s and never written.
+ */
+use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
+use crate::types::tag::normalize_custom_tags;
+use crate::types::{
+	character::Character, chat::Chat, error::HampterError, favourite::Favourites, persona::Persona,
+	profile::Profile, tag::Tag,
+};
+use futures::TryStreamExt;
+use getters2::Getters;
+use std::{
+	collections::BTreeMap,
+	fmt,
+	path::{Path, PathBuf},
+};
+
+/**
+ * Version of the archive layout, archives of newer versions are refused by `Backup::open`
+ */
+pub const BACKUP_VERSION: u32 = 1;
+
+/**
+ * Where avatars referenced by file name are served from
+ */
+const AVATAR_URL: &str = "https://ella.janitorai.com/avatars/";
+
+/**
+ * What to include besides the profile, personas, followed tags and favourites
+ * Chats and own characters can't be listed through the API yet, so they're named explicitly.
+ * The default options back up no chats and no characters at all.
+ */
+#[derive(Clone, Default)]
+pub struct BackupOptions {
+	chats: Vec<u64>,
+	characters: Vec<String>,
+	avatars: bool,
+}
+
+impl BackupOptions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn chat(mut self, id: u64) -> Self {
+		self.chats.push(id);
+		self
+	}
+
+	pub fn character(mut self, id: &str) -> Self {
+		self.characters.push(id.to_string());
+		self
+	}
+
+	/**
+	 * Download the avatars of the profile, personas and characters
+	 */
+	pub fn avatars(mut self, avatars: bool) -> Self {
+		self.avatars = avatars;
+		self
+	}
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct Manifest {
+	version: u32,
+	created_at: chrono::DateTime<chrono::Utc>,
+	profile_id: String,
+	chats: Vec<u64>,
+	characters: Vec<String>,
+	avatars: Vec<String>,  // file names in `avatars/`
+	warnings: Vec<String>, // what couldn't be backed up and why
+}
+
+#[derive(Getters)]
+pub struct Backup {
+	manifest: Manifest,
+	profile: Profile,
+	personas: Vec<Persona>,
+	following_tags: Vec<String>,
+	favourites: Vec<Character>,
+	chats: Vec<Chat>,
+	characters: Vec<Character>,
+	avatars: BTreeMap<String, Vec<u8>>,
+}
+
+impl Backup {
+	/**
+	 * Collect everything of the current user
+	 * Chats, characters and avatars that can't be fetched are noted in `Manifest::warnings`.
+	 */
+	pub async fn create<T: HttpTransport>(
+		options: &BackupOptions,
+		client: &AuthorizedClient<T>,
+	) -> Result<Backup, HampterError> {
+		let profile = Profile::get(client, None).await?;
+		let personas = Persona::list(client).await?;
+		let following_tags = Tag::get_following_tags(client).await?;
+		let favourites: Vec<Character> = Favourites::stream(client).try_collect().await?;
+
+		let mut warnings = vec![];
+		if options.chats.is_empty() {
+			warnings.push("no chats named in the backup options, none included".to_string());
+		}
+		let mut chats = vec![];
+		for id in &options.chats {
+			match Chat::get(*id, client).await {
+				Ok(chat) => chats.push(chat),
+				Err(e) => warnings.push(format!("chat {}: {}", id, e)),
+			}
+		}
+		let mut characters = vec![];
+		for id in &options.characters {
+			match Character::get(id, client).await {
+				Ok(character) => characters.push(character),
+				Err(e) => warnings.push(format!("character {}: {}", id, e)),
+			}
+		}
+
+		let mut avatars = BTreeMap::new();
+		if options.avatars {
+			let names = std::iter::once(profile.avatar_ref())
+				.chain(
+					personas
+						.iter()
+						.filter_map(|p: &Persona| p.avatar_ref().as_ref()),
+				)
+				.chain(characters.iter().map(|c: &Character| c.avatar_ref()))
+				.filter(|a: &&String| !a.is_empty());
+			for avatar in names {
+				let name = avatar_file_name(avatar);
+				if avatars.contains_key(&name) {
+					continue;
+				}
+				match download_avatar(avatar, client).await {
+					Ok(bytes) => {
+						avatars.insert(name, bytes);
+					}
+					Err(e) => warnings.push(format!("avatar {}: {}", avatar, e)),
+				}
+			}
+		}
+
+		Ok(Backup {
+			manifest: Manifest {
+				version: BACKUP_VERSION,
+				created_at: chrono::Utc::now(),
+				profile_id: profile.id_ref().clone(),
+				chats: chats
+					.iter()
+					.map(|c: &Chat| *c.chat_ref().id_ref() as u64)
+					.collect(),
+				characters: characters
+					.iter()
+					.map(|c: &Character| c.id_ref().clone())
+					.collect(),
+				avatars: avatars.keys().cloned().collect(),
+				warnings,
+			},
+			profile,
+			personas,
+			following_tags,
+			favourites,
+			chats,
+			characters,
+			avatars,
+		})
+	}
+
+	/**
+	 * Write the archive into the directory `dir`, creating it if needed
+	 */
+	pub fn save(&self, dir: impl AsRef<Path>) -> Result<(), HampterError> {
+		let dir = dir.as_ref();
+		for sub in ["chats", "characters", "avatars"] {
+			std::fs::create_dir_all(dir.join(sub))?;
+		}
+		write_json(dir.join("manifest.json"), &self.manifest)?;
+		write_json(dir.join("profile.json"), &self.profile)?;
+		write_json(dir.join("personas.json"), &self.personas)?;
+		write_json(dir.join("following_tags.json"), &self.following_tags)?;
+		write_json(dir.join("favourites.json"), &self.favourites)?;
+		for chat in &self.chats {
+			write_json(
+				dir.join("chats")
+					.join(format!("{}.json", chat.chat_ref().id_ref())),
+				chat,
+			)?;
+		}
+		for character in &self.characters {
+			write_json(
+				dir.join("characters")
+					.join(format!("{}.json", character.id_ref())),
+				character,
+			)?;
+		}
+		for (name, bytes) in &self.avatars {
+			std::fs::write(dir.join("avatars").join(name), bytes)?;
+		}
+		Ok(())
+	}
+
+	pub fn open(dir: impl AsRef<Path>) -> Result<Backup, HampterError> {
+		let dir = dir.as_ref();
+		let manifest: Manifest = read_json(dir.join("manifest.json"))?;
+		if manifest.version > BACKUP_VERSION {
+			return Err(HampterError::InvalidInput(format!(
+				"archive version {} is newer than the supported version {}",
+				manifest.version, BACKUP_VERSION
+			)));
+		}
+		let chats = manifest
+			.chats
+			.iter()
+			.map(|id: &u64| read_json(dir.join("chats").join(format!("{}.json", id))))
+			.collect::<Result<Vec<Chat>, HampterError>>()?;
+		let characters = manifest
+			.characters
+			.iter()
+			.map(|id: &String| read_json(dir.join("characters").join(format!("{}.json", id))))
+			.collect::<Result<Vec<Character>, HampterError>>()?;
+		let avatars = manifest
+			.avatars
+			.iter()
+			.map(|name: &String| Ok((name.clone(), std::fs::read(dir.join("avatars").join(name))?)))
+			.collect::<Result<BTreeMap<String, Vec<u8>>, HampterError>>()?;
+		Ok(Backup {
+			profile: read_json(dir.join("profile.json"))?,
+			personas: read_json(dir.join("personas.json"))?,
+			following_tags: read_json(dir.join("following_tags.json"))?,
+			favourites: read_json(dir.join("favourites.json"))?,
+			manifest,
+			chats,
+			characters,
+			avatars,
+		})
+	}
+
+	/**
+	 * Re-create what the API allows on the current account: personas, followed tags, favourites
+	 * and chats (as new chats with the same messages). With `dry_run` nothing is changed and the
+	 * report lists what would be done. Items already present on the account are skipped, except
+	 * chats: the API can't list them, so every chat is re-created, even one restored before.
+	 */
+	pub async fn restore<T: HttpTransport>(
+		&self,
+		dry_run: bool,
+		client: &AuthorizedClient<T>,
+	) -> Result<RestoreReport, HampterError> {
+		let mut report = RestoreReport {
+			dry_run,
+			actions: vec![],
+		};
+
+		let existing = Persona::list(client).await?;
+		for persona in &self.personas {
+			let item = format!("persona {}", persona.name_ref());
+			let exists = existing.iter().any(|p: &Persona| {
+				p.name_ref() == persona.name_ref() && p.appearance_ref() == persona.appearance_ref()
+			});
+			let outcome = if exists {
+				RestoreOutcome::Skipped("already exists".to_string())
+			} else if dry_run {
+				RestoreOutcome::Planned
+			} else {
+				match Persona::create(
+					persona.name_ref(),
+					persona.appearance_ref(),
+					persona.avatar_ref().as_deref(),
+					client,
+				)
+				.await
+				{
+					Ok(created) => RestoreOutcome::Restored(created.id_ref().clone()),
+					Err(e) => RestoreOutcome::Failed(e.to_string()),
+				}
+			};
+			report.push(item, outcome);
+		}
+
+		let following = normalize_custom_tags(Tag::get_following_tags(client).await?);
+		let missing: Vec<String> = normalize_custom_tags(self.following_tags.clone())
+			.into_iter()
+			.filter(|t: &String| !following.contains(t))
+			.collect();
+		let outcome = if missing.is_empty() {
+			RestoreOutcome::Skipped("already followed".to_string())
+		} else if dry_run {
+			RestoreOutcome::Planned
+		} else {
+			match Tag::update_following(
+				client,
+				|tags: &mut Vec<String>| {
+					let new: Vec<String> = missing
+						.iter()
+						.filter(|t: &&String| !tags.contains(t))
+						.cloned()
+						.collect();
+					tags.extend(new);
+				},
+				|tags: &[String]| missing.iter().all(|t: &String| tags.contains(t)),
+			)
+			.await
+			{
+				Ok(_) => RestoreOutcome::Restored(format!("{} tags", missing.len())),
+				Err(e) => RestoreOutcome::Failed(e.to_string()),
+			}
+		};
+		report.push("followed tags".to_string(), outcome);
+
+		let favourites: Vec<Character> = Favourites::stream(client).try_collect().await?;
+		for character in &self.favourites {
+			let item = format!("favourite {}", character.name_ref());
+			let outcome = if favourites
+				.iter()
+				.any(|f: &Character| f.id_ref() == character.id_ref())
+			{
+				RestoreOutcome::Skipped("already a favourite".to_string())
+			} else if dry_run {
+				RestoreOutcome::Planned
+			} else {
+				match Character::favourite(character.id_ref(), client).await {
+					Ok(()) => RestoreOutcome::Restored(character.id_ref().clone()),
+					Err(e) => RestoreOutcome::Failed(e.to_string()),
+				}
+			};
+			report.push(item, outcome);
+		}
+
+		for chat in &self.chats {
+			let item = format!(
+				"chat {} with {} (always re-created)",
+				chat.chat_ref().id_ref(),
+				chat.character_ref().name_ref()
+			);
+			let outcome = if dry_run {
+				RestoreOutcome::Planned
+			} else {
+				match Chat::replay(
+					chat.chat_ref().character_id_ref(),
+					chat.chat_messages_ref().clone(),
+					client,
+				)
+				.await
+				{
+					Ok(created) => {
+						RestoreOutcome::Restored(created.chat_ref().id_ref().to_string())
+					}
+					Err(e) => RestoreOutcome::Failed(e.to_string()),
+				}
+			};
+			report.push(item, outcome);
+		}
+
+		report.push(
+			"profile".to_string(),
+			RestoreOutcome::Skipped(
+				"apply profile.json with `Profile::update` if wanted".to_string(),
+			),
+		);
+		for character in &self.characters {
+			report.push(
+				format!("character {}", character.name_ref()),
+				RestoreOutcome::Skipped("characters can't be created through the API".to_string()),
+			);
+		}
+		Ok(report)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RestoreOutcome {
+	Planned,          // dry run only
+	Restored(String), // id of what was created
+	Skipped(String),
+	Failed(String),
+}
+
+#[derive(Clone, Debug, Getters)]
+pub struct RestoreAction {
+	item: String,
+	outcome: RestoreOutcome,
+}
+
+#[derive(Clone, Debug, Getters)]
+pub struct RestoreReport {
+	dry_run: bool,
+	actions: Vec<RestoreAction>,
+}
+
+impl RestoreReport {
+	pub fn failed(&self) -> impl Iterator<Item = &RestoreAction> {
+		self.actions
+			.iter()
+			.filter(|a: &&RestoreAction| matches!(a.outcome, RestoreOutcome::Failed(_)))
+	}
+
+	fn push(&mut self, item: String, outcome: RestoreOutcome) {
+		self.actions.push(RestoreAction { item, outcome });
+	}
+}
+
+impl fmt::Display for RestoreReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for action in &self.actions {
+			match &action.outcome {
+				RestoreOutcome::Planned => writeln!(f, "would restore {}", action.item)?,
+				RestoreOutcome::Restored(id) => writeln!(f, "restored {} as {}", action.item, id)?,
+				RestoreOutcome::Skipped(reason) => {
+					writeln!(f, "skipped {}: {}", action.item, reason)?
+				}
+				RestoreOutcome::Failed(error) => writeln!(f, "failed {}: {}", action.item, error)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+fn write_json<V: serde::Serialize + ?Sized>(path: PathBuf, value: &V) -> Result<(), HampterError> {
+	Ok(std::fs::write(path, serde_json::to_vec_pretty(value)?)?)
+}
+
+fn read_json<V: serde::de::DeserializeOwned>(path: PathBuf) -> Result<V, HampterError> {
+	Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+/**
+ * The last path segment, so avatars given as URL and as file name end up in the same file
+ */
+fn avatar_file_name(avatar: &str) -> String {
+	let name = avatar.split(['?', '#']).next().unwrap_or(avatar);
+	name.rsplit('/').next().unwrap_or(name).to_string()
+}
+
+async fn download_avatar<T: HttpTransport>(
+	avatar: &str,
+	client: &AuthorizedClient<T>,
+) -> Result<Vec<u8>, HampterError> {
+	let url = if avatar.starts_with("http://") || avatar.starts_with("https://") {
+		avatar.to_string()
+	} else {
+		format!("{}{}", AVATAR_URL, avatar)
+	};
+	client.send(client.get(url)?).await?.bytes().await
+}
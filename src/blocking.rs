@@ -0,0 +1,477 @@
+/*!
+ * Synchronous mirror of the async API
+ * Every call drives the async implementation on a runtime owned by the client,
+ * so none of these may be used from within another tokio runtime.
+ */
+use crate::auth::{self, Claims, User};
+use crate::transport::{HttpTransport, ReqwestTransport};
+use crate::types::{
+	character::{self, QueryResponse, SortMode},
+	chat::{self, GenerationMode, Message},
+	error::HampterError,
+	favourite, persona, profile, review, sillytavern, tag,
+};
+use futures::{Stream, StreamExt};
+use std::{future::Future, io, pin::Pin};
+
+/**
+ * The blocking counterpart of `auth::AuthorizedClient`
+ */
+pub struct AuthorizedClient<T: HttpTransport = ReqwestTransport> {
+	inner: auth::AuthorizedClient<T>,
+	runtime: tokio::runtime::Runtime,
+}
+
+impl AuthorizedClient {
+	pub fn new(
+		user_agent: &str,
+		cf_clearance: &str,
+		auth_token: &str,
+		refresh_token: &str,
+		x_app_version: &str,
+		api_key: &str,
+	) -> Result<AuthorizedClient, HampterError> {
+		Self::from_async(auth::AuthorizedClient::new(
+			user_agent,
+			cf_clearance,
+			auth_token,
+			refresh_token,
+			x_app_version,
+			api_key,
+		)?)
+	}
+
+	pub fn login_with_password(
+		user_agent: &str,
+		cf_clearance: &str,
+		x_app_version: &str,
+		api_key: &str,
+		email: &str,
+		password: &str,
+		auth_url: Option<&str>,
+	) -> Result<AuthorizedClient, HampterError> {
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.build()?;
+		let inner = runtime.block_on(auth::AuthorizedClient::login_with_password(
+			user_agent,
+			cf_clearance,
+			x_app_version,
+			api_key,
+			email,
+			password,
+			auth_url,
+		))?;
+		Ok(AuthorizedClient { inner, runtime })
+	}
+}
+
+impl<T: HttpTransport> AuthorizedClient<T> {
+	/**
+	 * Wrap an existing async client
+	 */
+	pub fn from_async(
+		client: auth::AuthorizedClient<T>,
+	) -> Result<AuthorizedClient<T>, HampterError> {
+		Ok(AuthorizedClient {
+			inner: client,
+			runtime: tokio::runtime::Builder::new_current_thread()
+				.enable_all()
+				.build()?,
+		})
+	}
+
+	pub fn inner(&self) -> &auth::AuthorizedClient<T> {
+		&self.inner
+	}
+
+	pub fn into_inner(self) -> auth::AuthorizedClient<T> {
+		self.inner
+	}
+
+	pub fn set_auth_url(&mut self, auth_url: &str) {
+		self.inner.set_auth_url(auth_url);
+	}
+
+	pub fn refresh_auth_token(&mut self) -> Result<(), HampterError> {
+		self.runtime.block_on(self.inner.refresh_auth_token())
+	}
+
+	pub fn logout(&mut self) -> Result<(), HampterError> {
+		self.runtime.block_on(self.inner.logout())
+	}
+
+	pub fn get_user(&self) -> Result<User, HampterError> {
+		self.block_on(self.inner.get_user())
+	}
+
+	pub fn claims(&self) -> Result<Claims, HampterError> {
+		self.inner.claims()
+	}
+
+	fn block_on<F: Future>(&self, future: F) -> F::Output {
+		self.runtime.block_on(future)
+	}
+}
+
+/**
+ * Lines of a generation stream, read one at a time
+ * Parse them with `MessageChunk::from_line` just like the async stream
+ */
+pub struct GenerationLines<'a> {
+	runtime: &'a tokio::runtime::Runtime,
+	lines: Pin<Box<dyn Stream<Item = io::Result<String>> + 'a>>,
+}
+
+impl Iterator for GenerationLines<'_> {
+	type Item = Result<String, HampterError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.runtime
+			.block_on(self.lines.next())
+			.map(|line: io::Result<String>| line.map_err(HampterError::from))
+	}
+}
+
+pub struct Chat;
+
+impl Chat {
+	pub fn get<T: HttpTransport>(
+		id: u64,
+		client: &AuthorizedClient<T>,
+	) -> Result<chat::Chat, HampterError> {
+		client.block_on(chat::Chat::get(id, &client.inner))
+	}
+
+	pub fn delete<T: HttpTransport>(
+		id: u64,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(chat::Chat::delete(id, &client.inner))
+	}
+
+	pub fn create<T: HttpTransport>(
+		character_id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<chat::Chat, HampterError> {
+		client.block_on(chat::Chat::create(character_id, &client.inner))
+	}
+
+	pub fn import_sillytavern<T: HttpTransport>(
+		character_id: &str,
+		transcript: &sillytavern::SillyTavernChat,
+		client: &AuthorizedClient<T>,
+	) -> Result<chat::Chat, HampterError> {
+		client.block_on(chat::Chat::import_sillytavern(
+			character_id,
+			transcript,
+			&client.inner,
+		))
+	}
+
+	pub fn generate<'a, T: HttpTransport>(
+		chat: &'a chat::Chat,
+		client: &'a AuthorizedClient<T>,
+		profile: &'a profile::Profile,
+		mode: Option<GenerationMode>,
+		message: Option<Message>,
+	) -> Result<GenerationLines<'a>, HampterError> {
+		Self::generate_with_config(chat, client, profile, profile.config_ref(), mode, message)
+	}
+
+	pub fn generate_with_config<'a, T: HttpTransport>(
+		chat: &'a chat::Chat,
+		client: &'a AuthorizedClient<T>,
+		profile: &'a profile::Profile,
+		config: &'a profile::Config,
+		mode: Option<GenerationMode>,
+		message: Option<Message>,
+	) -> Result<GenerationLines<'a>, HampterError> {
+		let lines = client.block_on(chat.generate_with_config(
+			&client.inner,
+			profile,
+			config,
+			mode,
+			message,
+		))?;
+		Ok(GenerationLines {
+			runtime: &client.runtime,
+			lines: Box::pin(lines),
+		})
+	}
+
+	pub fn send_message<T: HttpTransport>(
+		chat: &mut chat::Chat,
+		message: Message,
+		client: &AuthorizedClient<T>,
+	) -> Result<Message, HampterError> {
+		client.block_on(chat.send_message(message, &client.inner))
+	}
+
+	pub fn edit_message<T: HttpTransport>(
+		chat: &mut chat::Chat,
+		message_id: u64,
+		content: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(chat.edit_message(message_id, content, &client.inner))
+	}
+
+	pub fn delete_messages<T: HttpTransport>(
+		chat: &mut chat::Chat,
+		message_ids: Vec<u64>,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(chat.delete_messages(message_ids, &client.inner))
+	}
+}
+
+pub struct Character;
+
+impl Character {
+	pub fn get<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<character::Character, HampterError> {
+		client.block_on(character::Character::get(id, &client.inner))
+	}
+
+	pub fn query<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+		page: u32,
+		nsfw: bool,
+		search: Option<&str>,
+		sort: Option<SortMode>,
+		tag_ids: Option<Vec<u32>>,
+		custom_tags: Option<Vec<&str>>,
+	) -> Result<QueryResponse, HampterError> {
+		client.block_on(character::Character::query(
+			&client.inner,
+			page,
+			nsfw,
+			search,
+			sort,
+			tag_ids,
+			custom_tags,
+		))
+	}
+
+	pub fn favourite<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(character::Character::favourite(id, &client.inner))
+	}
+
+	pub fn unfavourite<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(character::Character::unfavourite(id, &client.inner))
+	}
+}
+
+pub struct Profile;
+
+impl Profile {
+	pub fn get<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+		id: Option<&str>,
+	) -> Result<profile::Profile, HampterError> {
+		client.block_on(profile::Profile::get(&client.inner, id))
+	}
+
+	pub fn update<T: HttpTransport>(
+		profile: &profile::Profile,
+		update: profile::ProfileUpdate,
+		client: &AuthorizedClient<T>,
+	) -> Result<profile::Profile, HampterError> {
+		client.block_on(profile.update(update, &client.inner))
+	}
+
+	pub fn update_config<T: HttpTransport>(
+		profile: &mut profile::Profile,
+		config: profile::Config,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(profile.update_config(config, &client.inner))
+	}
+
+	pub fn update_block_list<T: HttpTransport>(
+		profile: &mut profile::Profile,
+		block_list: profile::BlockList,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(profile.update_block_list(block_list, &client.inner))
+	}
+}
+
+pub struct Persona;
+
+impl Persona {
+	pub fn list<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<persona::Persona>, HampterError> {
+		client.block_on(persona::Persona::list(&client.inner))
+	}
+
+	pub fn get<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<persona::Persona, HampterError> {
+		client.block_on(persona::Persona::get(id, &client.inner))
+	}
+
+	pub fn create<T: HttpTransport>(
+		name: &str,
+		appearance: &str,
+		avatar: Option<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<persona::Persona, HampterError> {
+		client.block_on(persona::Persona::create(
+			name,
+			appearance,
+			avatar,
+			&client.inner,
+		))
+	}
+}
+
+pub struct Tag;
+
+impl Tag {
+	pub fn get_tag_list<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<tag::Tag>, HampterError> {
+		client.block_on(tag::Tag::get_tag_list(&client.inner))
+	}
+
+	pub fn get_following_tags<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
+		client.block_on(tag::Tag::get_following_tags(&client.inner))
+	}
+
+	pub fn follow_tags<T: HttpTransport>(
+		tags: Vec<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(tag::Tag::follow_tags(tags, &client.inner))
+	}
+
+	pub fn follow<T: HttpTransport>(
+		tag: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
+		client.block_on(tag::Tag::follow(tag, &client.inner))
+	}
+
+	pub fn unfollow<T: HttpTransport>(
+		tag: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
+		client.block_on(tag::Tag::unfollow(tag, &client.inner))
+	}
+
+	pub fn get_tag_suggestion<T: HttpTransport>(
+		prefix: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
+		client.block_on(tag::Tag::get_tag_suggestion(prefix, &client.inner))
+	}
+}
+
+pub struct Review;
+
+impl Review {
+	pub fn get<T: HttpTransport>(
+		character_id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<review::Review>, HampterError> {
+		client.block_on(review::Review::get(character_id, &client.inner))
+	}
+
+	pub fn get_page<T: HttpTransport>(
+		character_id: &str,
+		page: u32,
+		sort: Option<review::ReviewSort>,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<review::Review>, HampterError> {
+		client.block_on(review::Review::get_page(
+			character_id,
+			page,
+			sort,
+			&client.inner,
+		))
+	}
+
+	pub fn create<T: HttpTransport>(
+		character_id: &str,
+		is_like: bool,
+		content: Option<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<review::Review, HampterError> {
+		client.block_on(review::Review::create(
+			character_id,
+			is_like,
+			content,
+			&client.inner,
+		))
+	}
+
+	pub fn update<T: HttpTransport>(
+		review: &review::Review,
+		is_like: bool,
+		content: Option<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<review::Review, HampterError> {
+		client.block_on(review.update(is_like, content, &client.inner))
+	}
+
+	pub fn delete<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(review::Review::delete(id, &client.inner))
+	}
+
+	pub fn like<T: HttpTransport>(
+		review: &mut review::Review,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(review.like(&client.inner))
+	}
+
+	pub fn unlike<T: HttpTransport>(
+		review: &mut review::Review,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client.block_on(review.unlike(&client.inner))
+	}
+
+	pub fn get_comments<T: HttpTransport>(
+		review: &review::Review,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<review::ReviewComment>, HampterError> {
+		client.block_on(review.get_comments(&client.inner))
+	}
+
+	pub fn comment<T: HttpTransport>(
+		review: &mut review::Review,
+		content: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<review::ReviewComment, HampterError> {
+		client.block_on(review.comment(content, &client.inner))
+	}
+}
+
+pub struct Favourites;
+
+impl Favourites {
+	pub fn list<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+		page: u32,
+	) -> Result<favourite::Favourites, HampterError> {
+		client.block_on(favourite::Favourites::list(&client.inner, page))
+	}
+}
@@ -0,0 +1,372 @@
+use crate::transport::{HttpRequest, HttpResponse, HttpTransport};
+use crate::types::error::HampterError;
+use base64::Engine;
+use futures::StreamExt;
+use getters2::Getters;
+use reqwest::{
+	header::{HeaderMap, HeaderName, HeaderValue},
+	StatusCode,
+};
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+use tokio_util::bytes::Bytes;
+
+const REDACTED: &str = "[REDACTED]";
+
+/**
+ * Headers carrying credentials, their values never reach the disk
+ */
+const SECRET_HEADERS: [&str; 4] = ["authorization", "cookie", "set-cookie", "apikey"];
+
+/**
+ * JSON keys carrying credentials, redacted anywhere in request and response bodies
+ */
+const SECRET_KEYS: [&str; 3] = ["access_token", "refresh_token", "password"];
+
+/**
+ * JSON keys of the LLM keys held in `Config`, only recorded if explicitly requested
+ */
+const CONFIG_SECRET_KEYS: [&str; 3] = ["openAIKey", "claudeApiKey", "reverseProxyKey"];
+
+/**
+ * A piece of a response body and the time that passed since the previous one
+ * Bodies that aren't valid UTF-8 are stored base64 encoded
+ */
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct Chunk {
+	delay_ms: u64,
+	data: String,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	base64: bool,
+}
+
+impl Chunk {
+	fn new(delay: Duration, bytes: &[u8]) -> Self {
+		match std::str::from_utf8(bytes) {
+			Ok(text) => Self {
+				delay_ms: delay.as_millis() as u64,
+				data: text.to_string(),
+				base64: false,
+			},
+			Err(_) => Self {
+				delay_ms: delay.as_millis() as u64,
+				data: base64::engine::general_purpose::STANDARD.encode(bytes),
+				base64: true,
+			},
+		}
+	}
+
+	fn bytes(&self) -> Result<Bytes, HampterError> {
+		if !self.base64 {
+			return Ok(Bytes::from(self.data.clone()));
+		}
+		base64::engine::general_purpose::STANDARD
+			.decode(&self.data)
+			.map(Bytes::from)
+			.map_err(|e| HampterError::Io(std::io::Error::other(e)))
+	}
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct Interaction {
+	method: String,
+	url: String,
+	request_headers: BTreeMap<String, String>,
+	request_body: Option<String>,
+	status: u16,
+	response_headers: BTreeMap<String, String>,
+	chunks: Vec<Chunk>,
+}
+
+/**
+ * Recorded request/response pairs in the order they happened
+ */
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize, Getters)]
+pub struct Cassette {
+	interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+	pub fn load(path: impl AsRef<Path>) -> Result<Cassette, HampterError> {
+		Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HampterError> {
+		Ok(std::fs::write(path, serde_json::to_vec_pretty(self)?)?)
+	}
+}
+
+/**
+ * Wraps a transport and writes every exchange to a cassette file
+ * Credentials in headers and bodies are redacted before anything is written.
+ * Responses are recorded once their body has been read to the end or dropped, so bodies that are
+ * never read (e.g. of deletes and error statuses) are recorded without chunks.
+ */
+pub struct Recorder<T: HttpTransport> {
+	inner: T,
+	path: PathBuf,
+	cassette: Arc<Mutex<Cassette>>,
+	include_secrets: bool,
+}
+
+impl<T: HttpTransport> Recorder<T> {
+	/**
+	 * Start a new cassette at `path`, overwriting an existing one
+	 */
+	pub fn new(inner: T, path: impl Into<PathBuf>) -> Self {
+		Self {
+			inner,
+			path: path.into(),
+			cassette: Arc::new(Mutex::new(Cassette::default())),
+			include_secrets: false,
+		}
+	}
+
+	/**
+	 * Record the LLM keys of `Config` instead of redacting them
+	 * Tokens, cookies and passwords are redacted regardless.
+	 */
+	pub fn with_secrets(mut self, include_secrets: bool) -> Self {
+		self.include_secrets = include_secrets;
+		self
+	}
+
+	pub fn inner(&self) -> &T {
+		&self.inner
+	}
+
+	/**
+	 * Write the cassette to disk
+	 * Happens automatically after every recorded response
+	 */
+	pub fn save(&self) -> Result<(), HampterError> {
+		self.cassette.lock().unwrap().save(&self.path)
+	}
+}
+
+impl<T: HttpTransport> HttpTransport for Recorder<T> {
+	async fn execute(&self, request: HttpRequest) -> Result<HttpResponse, HampterError> {
+		let mut interaction = Interaction {
+			method: request.method().to_string(),
+			url: request.url().to_string(),
+			request_headers: redact_headers(request.headers()),
+			request_body: request
+				.body()
+				.map(|body: &[u8]| redact_body(body, self.include_secrets)),
+			status: 0,
+			response_headers: BTreeMap::new(),
+			chunks: vec![],
+		};
+		let response = self.inner.execute(request).await?;
+		interaction.status = response.status().as_u16();
+		interaction.response_headers = redact_headers(response.headers());
+
+		let status = response.status();
+		let headers = response.headers().clone();
+		let mut body = response.bytes_stream();
+		// moved into the stream so it is recorded even if the stream is never polled
+		let mut pending = PendingInteraction {
+			interaction: Some(interaction),
+			chunks: vec![],
+			cassette: self.cassette.clone(),
+			path: self.path.clone(),
+			include_secrets: self.include_secrets,
+		};
+		let recorded = async_stream::stream! {
+			let mut last = Instant::now();
+			while let Some(chunk) = body.next().await {
+				if let Ok(bytes) = &chunk {
+					pending.chunks.push((last.elapsed(), bytes.clone()));
+					last = Instant::now();
+				}
+				yield chunk;
+			}
+			drop(pending);
+		};
+		Ok(HttpResponse::new(status, headers, Box::pin(recorded)))
+	}
+}
+
+/**
+ * An exchange whose body is still being read, added to the cassette when dropped
+ */
+struct PendingInteraction {
+	interaction: Option<Interaction>,
+	chunks: Vec<(Duration, Bytes)>,
+	cassette: Arc<Mutex<Cassette>>,
+	path: PathBuf,
+	include_secrets: bool,
+}
+
+impl Drop for PendingInteraction {
+	fn drop(&mut self) {
+		let Some(mut interaction) = self.interaction.take() else {
+			return;
+		};
+		interaction.chunks = redact_chunks(std::mem::take(&mut self.chunks), self.include_secrets);
+		if let Ok(mut cassette) = self.cassette.lock() {
+			cassette.interactions.push(interaction);
+			let _ = cassette.save(&self.path); // nothing to report the error to, see `Recorder::save`
+		}
+	}
+}
+
+/**
+ * Serves the responses of a cassette without touching the network
+ * Requests are matched by method and URL, each interaction is served once in recorded order.
+ */
+pub struct Replayer {
+	interactions: Mutex<Vec<Option<Interaction>>>,
+	timing: bool,
+}
+
+impl Replayer {
+	pub fn new(cassette: Cassette) -> Self {
+		Self {
+			interactions: Mutex::new(cassette.interactions.into_iter().map(Some).collect()),
+			timing: false,
+		}
+	}
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, HampterError> {
+		Ok(Self::new(Cassette::load(path)?))
+	}
+
+	/**
+	 * Reproduce the delays between recorded chunks, e.g. to test live previews of generations
+	 */
+	pub fn with_timing(mut self, timing: bool) -> Self {
+		self.timing = timing;
+		self
+	}
+
+	/**
+	 * Number of recorded interactions that haven't been served yet
+	 */
+	pub fn remaining(&self) -> usize {
+		self.interactions
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|i: &&Option<Interaction>| i.is_some())
+			.count()
+	}
+}
+
+impl HttpTransport for Replayer {
+	async fn execute(&self, request: HttpRequest) -> Result<HttpResponse, HampterError> {
+		let method = request.method().to_string();
+		let interaction = self
+			.interactions
+			.lock()
+			.unwrap()
+			.iter_mut()
+			.find(|i: &&mut Option<Interaction>| {
+				i.as_ref()
+					.is_some_and(|i: &Interaction| i.method == method && i.url == request.url())
+			})
+			.and_then(Option::take)
+			.ok_or_else(|| HampterError::NotRecorded(format!("{} {}", method, request.url())))?;
+
+		let mut headers = HeaderMap::new();
+		for (name, value) in &interaction.response_headers {
+			if let (Ok(name), Ok(value)) = (
+				HeaderName::from_bytes(name.as_bytes()),
+				HeaderValue::from_str(value),
+			) {
+				headers.append(name, value);
+			}
+		}
+		let chunks = interaction
+			.chunks
+			.iter()
+			.map(|c: &Chunk| Ok((Duration::from_millis(c.delay_ms), c.bytes()?)))
+			.collect::<Result<Vec<(Duration, Bytes)>, HampterError>>()?;
+		let timing = self.timing;
+		let body = async_stream::stream! {
+			for (delay, bytes) in chunks {
+				if timing {
+					tokio::time::sleep(delay).await;
+				}
+				yield Ok(bytes);
+			}
+		};
+		Ok(HttpResponse::new(
+			StatusCode::from_u16(interaction.status).unwrap_or(StatusCode::OK),
+			headers,
+			Box::pin(body),
+		))
+	}
+}
+
+fn redact_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+	headers
+		.iter()
+		.map(|(name, value)| {
+			let value = if SECRET_HEADERS.contains(&name.as_str()) {
+				REDACTED.to_string()
+			} else {
+				String::from_utf8_lossy(value.as_bytes()).to_string()
+			};
+			(name.to_string(), value)
+		})
+		.collect()
+}
+
+fn redact_body(body: &[u8], include_secrets: bool) -> String {
+	match serde_json::from_slice::<serde_json::Value>(body) {
+		Ok(mut json) => {
+			redact_json(&mut json, include_secrets);
+			json.to_string()
+		}
+		Err(_) => String::from_utf8_lossy(body).to_string(),
+	}
+}
+
+fn redact_json(value: &mut serde_json::Value, include_secrets: bool) {
+	match value {
+		serde_json::Value::Object(map) => {
+			for (key, value) in map.iter_mut() {
+				if SECRET_KEYS.contains(&key.as_str())
+					|| (!include_secrets
+						&& CONFIG_SECRET_KEYS.contains(&key.as_str())
+						&& !value.is_null())
+				{
+					*value = serde_json::Value::String(REDACTED.to_string());
+				} else {
+					redact_json(value, include_secrets);
+				}
+			}
+		}
+		serde_json::Value::Array(values) => values
+			.iter_mut()
+			.for_each(|value: &mut serde_json::Value| redact_json(value, include_secrets)),
+		_ => {}
+	}
+}
+
+/**
+ * JSON bodies are merged into a single redacted chunk, streamed bodies keep their chunks and timing
+ * The raw bytes are joined first, chunk borders may split a UTF-8 character.
+ */
+fn redact_chunks(chunks: Vec<(Duration, Bytes)>, include_secrets: bool) -> Vec<Chunk> {
+	let body: Vec<u8> = chunks
+		.iter()
+		.flat_map(|(_, bytes)| bytes.iter().copied())
+		.collect();
+	match serde_json::from_slice::<serde_json::Value>(&body) {
+		Ok(mut json) => {
+			redact_json(&mut json, include_secrets);
+			let delay = chunks.iter().map(|(delay, _)| *delay).sum();
+			vec![Chunk::new(delay, json.to_string().as_bytes())]
+		}
+		Err(_) => chunks
+			.iter()
+			.map(|(delay, bytes)| Chunk::new(*delay, bytes))
+			.collect(),
+	}
+}
@@ -0,0 +1,182 @@
+use crate::types::character::{Character, QueryResponse};
+use crate::types::error::HampterError;
+use futures::{Stream, StreamExt};
+use getters2::Getters;
+use regex::Regex;
+use std::fmt;
+
+/**
+ * Why a character didn't pass a `ContentFilter`
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum DropReason {
+	Tag(String),
+	CustomTag(String),
+	Creator(String),
+	Keyword(String), // the pattern that matched
+	Nsfw,
+	ImageNsfw,
+}
+
+impl fmt::Display for DropReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Tag(tag) => write!(f, "excluded tag `{}`", tag),
+			Self::CustomTag(tag) => write!(f, "excluded custom tag `{}`", tag),
+			Self::Creator(id) => write!(f, "excluded creator `{}`", id),
+			Self::Keyword(pattern) => write!(f, "matched keyword `{}`", pattern),
+			Self::Nsfw => f.write_str("character is NSFW"),
+			Self::ImageNsfw => f.write_str("avatar is NSFW"),
+		}
+	}
+}
+
+/**
+ * A character removed by a filter and every rule it broke
+ */
+#[derive(Clone, Debug, Getters)]
+pub struct Dropped {
+	id: String,
+	name: String,
+	reasons: Vec<DropReason>,
+}
+
+/**
+ * Client side filter for search results, independent of the block list stored on the server
+ * Tags match tag IDs, names and slugs case-insensitively, keywords are regular expressions
+ * matched against name and description (prefix them with `(?i)` to ignore case).
+ */
+#[derive(Clone, Default)]
+pub struct ContentFilter {
+	tags: Vec<String>,
+	custom_tags: Vec<String>,
+	creators: Vec<String>,
+	keywords: Vec<Regex>,
+	block_nsfw: bool,
+	block_image_nsfw: bool,
+}
+
+impl ContentFilter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn exclude_tag(mut self, tag: &str) -> Self {
+		self.tags.push(tag.to_lowercase());
+		self
+	}
+
+	pub fn exclude_custom_tag(mut self, tag: &str) -> Self {
+		self.custom_tags.push(tag.to_lowercase());
+		self
+	}
+
+	pub fn exclude_creator(mut self, creator_id: &str) -> Self {
+		self.creators.push(creator_id.to_string());
+		self
+	}
+
+	pub fn exclude_keyword(mut self, pattern: &str) -> Result<Self, HampterError> {
+		let regex = Regex::new(pattern)
+			.map_err(|e: regex::Error| HampterError::InvalidInput(e.to_string()))?;
+		self.keywords.push(regex);
+		Ok(self)
+	}
+
+	pub fn block_nsfw(mut self, block: bool) -> Self {
+		self.block_nsfw = block;
+		self
+	}
+
+	/**
+	 * Drop characters whose avatar is NSFW, characters without the flag pass
+	 */
+	pub fn block_image_nsfw(mut self, block: bool) -> Self {
+		self.block_image_nsfw = block;
+		self
+	}
+
+	/**
+	 * Every rule the character breaks, empty if it passes
+	 */
+	pub fn check(&self, character: &Character) -> Vec<DropReason> {
+		let mut reasons = vec![];
+		if self.block_nsfw && *character.is_nsfw_ref() {
+			reasons.push(DropReason::Nsfw);
+		}
+		if self.block_image_nsfw && character.is_image_nsfw_ref().unwrap_or(false) {
+			reasons.push(DropReason::ImageNsfw);
+		}
+		if self.creators.contains(character.creator_id_ref()) {
+			reasons.push(DropReason::Creator(character.creator_id_ref().clone()));
+		}
+		for tag in character.tags_matching(&self.tags) {
+			reasons.push(DropReason::Tag(tag.name_ref().clone()));
+		}
+		for tag in character.custom_tags_matching(&self.custom_tags) {
+			reasons.push(DropReason::CustomTag(tag.clone()));
+		}
+		for keyword in &self.keywords {
+			if keyword.is_match(character.name_ref())
+				|| keyword.is_match(character.description_ref())
+			{
+				reasons.push(DropReason::Keyword(keyword.as_str().to_string()));
+			}
+		}
+		reasons
+	}
+
+	/**
+	 * Remove every character that doesn't pass from `QueryResponse::data`
+	 */
+	pub fn apply(&self, response: &mut QueryResponse) -> Vec<Dropped> {
+		let mut dropped = vec![];
+		response.retain(|c: &Character| match self.audit(c) {
+			Some(d) => {
+				dropped.push(d);
+				false
+			}
+			None => true,
+		});
+		dropped
+	}
+
+	/**
+	 * Filter a stream of characters like `Character::search_stream`
+	 * `on_drop` is called for every character removed
+	 */
+	pub fn filter_stream<'a, S, E>(
+		&'a self,
+		stream: S,
+		mut on_drop: impl FnMut(Dropped) + 'a,
+	) -> impl Stream<Item = Result<Character, E>> + 'a
+	where
+		S: Stream<Item = Result<Character, E>> + 'a,
+	{
+		stream.filter(move |res: &Result<Character, E>| {
+			let keep = match res {
+				Ok(c) => match self.audit(c) {
+					Some(d) => {
+						on_drop(d);
+						false
+					}
+					None => true,
+				},
+				Err(_) => true,
+			};
+			futures::future::ready(keep)
+		})
+	}
+
+	fn audit(&self, character: &Character) -> Option<Dropped> {
+		let reasons = self.check(character);
+		if reasons.is_empty() {
+			return None;
+		}
+		Some(Dropped {
+			id: character.id_ref().clone(),
+			name: character.name_ref().clone(),
+			reasons,
+		})
+	}
+}
@@ -1,6 +1,490 @@
+#![allow(clippy::tabs_in_doc_comments)]
+
 pub mod types;
 pub mod auth;
+pub mod backup;
+pub mod blocking;
+pub mod cassette;
+pub mod filter;
+pub mod pool;
+#[cfg(feature = "store")]
+pub mod store;
+pub mod transport;
 
 #[cfg(test)]
 mod tests {
+	use crate::auth::AuthorizedClient;
+	use crate::cassette::{Cassette, Recorder, Replayer};
+	use crate::transport::{HttpRequest, HttpResponse, HttpTransport};
+	use crate::types::schema::{self, Modeled, SchemaMode};
+	use crate::types::{
+		character, chat, chat::Chat, error::HampterError, persona, profile, review,
+		sillytavern::SillyTavernChat,
+	};
+	use futures::TryStreamExt;
+	use reqwest::{header::HeaderMap, Method, StatusCode};
+	use serde_json::json;
+	use std::collections::BTreeSet;
+	use std::sync::atomic::{AtomicU32, Ordering};
+	use tokio_util::bytes::Bytes;
+
+	/**
+	 * Answers deletes with 204, posts with a token split inside a UTF-8 character and
+	 * everything else with 404
+	 */
+	struct FakeTransport;
+
+	impl HttpTransport for FakeTransport {
+		async fn execute(&self, request: HttpRequest) -> Result<HttpResponse, HampterError> {
+			let status = match *request.method() {
+				Method::DELETE => StatusCode::NO_CONTENT,
+				Method::POST => {
+					let body = r#"{"name":"Chloé","access_token":"secret-token"}"#.as_bytes();
+					let split = body.iter().position(|b: &u8| *b >= 0x80).unwrap() + 1;
+					let chunks = vec![
+						Ok(Bytes::copy_from_slice(&body[..split])),
+						Ok(Bytes::copy_from_slice(&body[split..])),
+					];
+					return Ok(HttpResponse::new(
+						StatusCode::OK,
+						HeaderMap::new(),
+						Box::pin(futures::stream::iter(chunks)),
+					));
+				}
+				_ => StatusCode::NOT_FOUND,
+			};
+			Ok(HttpResponse::from_bytes(status, HeaderMap::new(), ""))
+		}
+	}
+
+	/**
+	 * Serves `count` reviews three per page, or all of them on every page if `paged` is false
+	 */
+	struct ReviewServer {
+		count: u32,
+		paged: bool,
+		requests: AtomicU32,
+	}
+
+	impl ReviewServer {
+		fn new(count: u32, paged: bool) -> Self {
+			Self {
+				count,
+				paged,
+				requests: AtomicU32::new(0),
+			}
+		}
+	}
+
+	impl HttpTransport for ReviewServer {
+		async fn execute(&self, request: HttpRequest) -> Result<HttpResponse, HampterError> {
+			self.requests.fetch_add(1, Ordering::SeqCst);
+			let url = reqwest::Url::parse(request.url()).unwrap();
+			let page: u32 = url
+				.query_pairs()
+				.find(|(k, _)| k == "page")
+				.map(|(_, v)| v.parse().unwrap())
+				.unwrap();
+			let ids = match self.paged {
+				true => (page - 1) * 3 + 1..=(page * 3).min(self.count),
+				false => 1..=self.count,
+			};
+			let reviews: Vec<serde_json::Value> = ids
+				.map(|id: u32| {
+					json!({
+						"id": id.to_string(), "user_id": "u", "character_id": "c",
+						"created_at": "2024-01-01T00:00:00Z", "is_like": true, "like_count": 0,
+						"comment_count": 0, "moderator": false,
+						"user_profiles": {"name": "User", "avatar": "", "is_verified": false},
+					})
+				})
+				.collect();
+			Ok(HttpResponse::from_bytes(
+				StatusCode::OK,
+				HeaderMap::new(),
+				serde_json::to_vec(&reviews).unwrap(),
+			))
+		}
+	}
+
+	fn client<T: HttpTransport>(transport: T) -> AuthorizedClient<T> {
+		AuthorizedClient::with_transport(transport, "agent", "clearance", "", "", "1", "key")
+	}
+
+	fn cassette_path(name: &str) -> std::path::PathBuf {
+		std::env::temp_dir().join(format!("hampter-{}-{}.json", name, std::process::id()))
+	}
+
+	#[tokio::test]
+	async fn cassette_round_trip_with_unread_bodies() {
+		let path = cassette_path("round-trip");
+		let recorder = client(Recorder::new(FakeTransport, path.clone()));
+		Chat::delete(1, &recorder).await.unwrap();
+		let error = Chat::get(2, &recorder).await.err().unwrap();
+		assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+
+		let cassette = Cassette::load(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(cassette.interactions_ref().len(), 2);
+
+		let replayer = client(Replayer::new(cassette));
+		Chat::delete(1, &replayer).await.unwrap();
+		let error = Chat::get(2, &replayer).await.err().unwrap();
+		assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+		assert_eq!(replayer.transport().remaining(), 0);
+	}
+
+	#[tokio::test]
+	async fn logout_clears_the_tokens() {
+		fn authorized(client: &AuthorizedClient<FakeTransport>) -> bool {
+			let request = client.get("https://janitorai.com").unwrap();
+			request.headers().contains_key("authorization")
+		}
+		let mut client = AuthorizedClient::with_transport(
+			FakeTransport,
+			"agent",
+			"clearance",
+			"bearer",
+			"refresh",
+			"1",
+			"key",
+		);
+		assert!(authorized(&client));
+		client.logout().await.unwrap();
+		assert!(!authorized(&client));
+	}
+
+	#[tokio::test]
+	async fn cassette_redacts_bodies_split_inside_a_character() {
+		let path = cassette_path("redact");
+		let recorder = client(Recorder::new(FakeTransport, path.clone()));
+		let request = recorder.post("https://auth.janitorai.com/token").unwrap();
+		let body = recorder.send(request).await.unwrap().bytes().await.unwrap();
+		assert!(String::from_utf8(body).unwrap().contains("secret-token"));
+
+		let written = std::fs::read_to_string(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(!written.contains("secret-token"));
+		assert!(written.contains("Chloé"));
+	}
+
+	/**
+	 * Deserializes `fixture` and compares the keys `M` serializes to with its `modeled!` list
+	 */
+	fn assert_modeled<M>(fixture: &serde_json::Value)
+	where
+		M: Modeled + serde::de::DeserializeOwned + serde::Serialize,
+	{
+		let model: M = serde_json::from_str(&fixture.to_string()).unwrap();
+		let serialized = serde_json::to_value(&model).unwrap();
+		let keys: BTreeSet<&str> = serialized
+			.as_object()
+			.unwrap()
+			.keys()
+			.map(String::as_str)
+			.collect();
+		let modeled: BTreeSet<&str> = M::FIELDS.iter().copied().collect();
+		assert_eq!(keys, modeled, "{}", M::NAME);
+	}
+
+	#[test]
+	fn modeled_fields_match_the_structs() {
+		let date = "2024-01-01T00:00:00Z";
+		let tag = json!({
+			"id": 1, "created_at": date, "name": "Fantasy", "slug": "fantasy", "description": "",
+		});
+		let stats = json!({"chat": 1, "message": 1});
+		let tokens = json!({
+			"scenario_tokens": 1, "personality_tokens": 1, "first_message_tokens": 1,
+			"example_dialog_tokens": 1, "total_tokens": 4,
+		});
+		let character = json!({
+			"id": "c", "name": "Bot", "avatar": "", "description": "", "created_at": date,
+			"updated_at": date, "first_published_at": date, "is_public": true,
+			"is_force_remove": false, "is_nsfw": false, "creator_id": "u", "creator_name": "User",
+			"creator_verified": false, "token_counts": tokens, "tags": [tag], "stats": stats,
+		});
+		let chat_character = json!({
+			"id": "c", "name": "Bot", "description": "", "avatar": "", "is_nsfw": false,
+			"is_public": true, "is_image_nsfw": false, "allow_proxy": true,
+		});
+		let message = json!({
+			"id": 1, "created_at": date, "is_bot": true, "is_main": true, "chat_id": 1,
+			"message": "Hi",
+		});
+		let delta = json!({"content": "Hi"});
+		let choice = json!({"index": 0, "delta": delta});
+		let chat_info = json!({
+			"id": 1, "is_public": false, "summary": "", "chat_count": "1", "updated_at": date,
+			"user_id": "u", "character_id": "c",
+		});
+		let settings = json!({"temperature": 1.1, "max_new_token": 500, "context_length": 16384});
+		let config = json!({
+			"chat_custom_background_image": "", "chat_custom_background_opacity": 10,
+			"chat_custom_background_blur": 0, "chat_custom_foreground_color": "#ffffff",
+			"chat_custom_font_size": 14, "show_clouds": false, "show_swords": false,
+			"generation_settings": settings, "api": "", "llm_prompt": "", "open_ai_mode": "api_key",
+			"text_streaming": true, "immersive_mode": false, "debug_mode": false,
+			"use_pygmalion_format": true,
+		});
+		let persona = json!({
+			"id": "p", "name": "Me", "appearance": "", "created_at": date, "updated_at": date,
+		});
+		let user_profile = json!({"name": "User", "avatar": "", "is_verified": false});
+
+		assert_modeled::<crate::types::tag::Tag>(&tag);
+		assert_modeled::<character::CharacterStats>(&stats);
+		assert_modeled::<character::TokenStats>(&tokens);
+		assert_modeled::<character::Character>(&character);
+		assert_modeled::<character::QueryResponse>(&json!({
+			"data": [character], "total": 1, "size": 1, "page": "1", "top_custom_tags": [],
+		}));
+		assert_modeled::<crate::types::favourite::Favourites>(&json!({
+			"data": [character], "total": 1, "size": 1, "page": 1,
+		}));
+		assert_modeled::<chat::Character>(&chat_character);
+		assert_modeled::<chat::Message>(&message);
+		assert_modeled::<chat::TextDelta>(&delta);
+		assert_modeled::<chat::TextChoice>(&choice);
+		assert_modeled::<chat::MessageChunk>(&json!({
+			"id": "g", "object": "chat.completion.chunk", "created": 1, "model": "m",
+			"choices": [choice],
+		}));
+		assert_modeled::<chat::ChatInfo>(&chat_info);
+		assert_modeled::<Chat>(&json!({
+			"chat": chat_info, "character": chat_character, "chatMessages": [message],
+		}));
+		assert_modeled::<profile::GenerationSettings>(&settings);
+		assert_modeled::<profile::Config>(&config);
+		assert_modeled::<profile::Persona>(&persona);
+		assert_modeled::<profile::BlockList>(&json!({}));
+		assert_modeled::<profile::Profile>(&json!({
+			"id": "u", "avatar": "", "name": "User", "user_name": "user", "about_me": "",
+			"is_verified": false, "followers_count": "0", "config": config, "profile": "",
+			"block_list": {}, "created_at": date,
+		}));
+		assert_modeled::<persona::Persona>(&persona);
+		assert_modeled::<review::UserProfile>(&user_profile);
+		assert_modeled::<review::Review>(&json!({
+			"id": "r", "user_id": "u", "character_id": "c", "created_at": date, "is_like": true,
+			"like_count": 0, "comment_count": 0, "user_profiles": user_profile, "moderator": false,
+		}));
+		assert_modeled::<review::ReviewComment>(&json!({
+			"id": "rc", "review_id": "r", "user_id": "u", "created_at": date, "content": "Agreed",
+			"user_profiles": user_profile,
+		}));
+		assert_modeled::<crate::auth::User>(&json!({
+			"id": "u", "aud": "authenticated", "role": "authenticated", "created_at": date,
+		}));
+	}
+
+	#[test]
+	fn schema_mode_only_applies_to_responses() {
+		let message = json!({
+			"id": 1, "created_at": "2024-01-01T00:00:00Z", "is_bot": true, "is_main": true,
+			"chat_id": 1, "message": "Hi", "new_field": 1,
+		})
+		.to_string();
+		schema::set_mode(SchemaMode::Strict);
+		let local = serde_json::from_str::<chat::Message>(&message);
+		let response = schema::in_response(|| serde_json::from_str::<chat::Message>(&message));
+		schema::set_mode(SchemaMode::Off);
+		assert!(local.is_ok());
+		assert!(response.is_err());
+	}
+
+	async fn streamed_review_ids(server: ReviewServer) -> (Vec<String>, u32) {
+		let client = client(server);
+		let reviews: Vec<review::Review> = review::Review::stream("c", None, None, None, &client)
+			.try_collect()
+			.await
+			.unwrap();
+		let ids = reviews
+			.iter()
+			.map(|r: &review::Review| r.id_ref().clone())
+			.collect();
+		(ids, client.transport().requests.load(Ordering::SeqCst))
+	}
+
+	#[tokio::test]
+	async fn review_stream_stops_when_the_server_ignores_pages() {
+		let (ids, requests) = streamed_review_ids(ReviewServer::new(4, false)).await;
+		assert_eq!(ids, ["1", "2", "3", "4"]);
+		assert_eq!(requests, 2);
+	}
+
+	#[tokio::test]
+	async fn review_stream_stops_after_a_short_page() {
+		let (ids, requests) = streamed_review_ids(ReviewServer::new(7, true)).await;
+		assert_eq!(ids, ["1", "2", "3", "4", "5", "6", "7"]);
+		assert_eq!(requests, 3);
+
+		let (ids, requests) = streamed_review_ids(ReviewServer::new(6, true)).await;
+		assert_eq!(ids.len(), 6);
+		assert_eq!(requests, 3); // the empty third page
+	}
+
+	/**
+	 * A chat with `messages` given as `(id, is_bot, is_main, text)`, sent a minute apart in that order
+	 */
+	fn chat_with(messages: &[(u64, bool, bool, &str)]) -> Chat {
+		let date = "2024-01-01T00:00:00Z";
+		let messages: Vec<serde_json::Value> = messages
+			.iter()
+			.map(|&(id, is_bot, is_main, text)| {
+				json!({
+					"id": id, "created_at": format!("2024-01-01T00:{:02}:00Z", id), "is_bot": is_bot,
+					"is_main": is_main, "chat_id": 1, "message": text,
+				})
+			})
+			.collect();
+		serde_json::from_value(json!({
+			"chat": {
+				"id": 1, "is_public": false, "summary": "", "chat_count": "1", "updated_at": date,
+				"user_id": "u", "character_id": "c",
+			},
+			"character": {
+				"id": "c", "name": "Bot", "description": "", "avatar": "", "is_nsfw": false,
+				"is_public": true, "is_image_nsfw": false, "allow_proxy": true,
+			},
+			"chatMessages": messages,
+		}))
+		.unwrap()
+	}
+
+	fn summarized(messages: &[chat::Message]) -> Vec<(bool, bool, &str)> {
+		messages
+			.iter()
+			.map(|m: &chat::Message| (*m.is_bot_ref(), *m.is_main_ref(), m.message_ref().as_str()))
+			.collect()
+	}
+
+	#[test]
+	fn sillytavern_round_trip_keeps_swipes_and_order() {
+		let chat = chat_with(&[
+			(1, false, true, "Hi"),
+			(2, true, true, "Hello"),
+			(3, true, false, "Hey"),
+			(4, true, true, "How are you?"),
+			(5, false, true, "Fine"),
+		]);
+		let jsonl = SillyTavernChat::from_chat(&chat, "User")
+			.to_jsonl()
+			.unwrap();
+		let transcript = SillyTavernChat::from_jsonl(&jsonl).unwrap();
+		assert_eq!(transcript.messages_ref().len(), 4);
+
+		let mut messages = transcript.to_messages(1);
+		messages.sort_by_key(|m: &chat::Message| *m.created_at_ref());
+		assert_eq!(
+			summarized(&messages),
+			[
+				(false, true, "Hi"),
+				(true, true, "Hello"),
+				(true, false, "Hey"),
+				(true, true, "How are you?"),
+				(false, true, "Fine"),
+			]
+		);
+
+		// a missing date doesn't move a message to the end
+		let missing = jsonl.replacen("\"send_date\":\"January 1, 2024 12:01am\",", "", 1);
+		assert_ne!(missing, jsonl);
+		let mut messages = SillyTavernChat::from_jsonl(&missing)
+			.unwrap()
+			.to_messages(1);
+		messages.sort_by_key(|m: &chat::Message| *m.created_at_ref());
+		assert_eq!(summarized(&messages)[0], (false, true, "Hi"));
+	}
+
+	#[cfg(feature = "store")]
+	#[test]
+	fn store_writes_only_what_changed() {
+		fn counts(stats: crate::store::SyncStats) -> (usize, usize, usize) {
+			(
+				*stats.added_ref(),
+				*stats.updated_ref(),
+				*stats.removed_ref(),
+			)
+		}
+		let store = crate::store::Store::in_memory().unwrap();
+		let chat = chat_with(&[
+			(1, false, true, "Hi"),
+			(2, true, true, "Hello"),
+			(3, false, true, "Bye"),
+		]);
+		assert_eq!(counts(store.save_chat(&chat).unwrap()), (3, 0, 0));
+		assert!(store.save_chat(&chat).unwrap().is_empty());
+
+		let edited = chat_with(&[
+			(1, false, true, "Hi"),
+			(2, true, true, "Hello there"),
+			(3, false, true, "Bye"),
+		]);
+		assert_eq!(counts(store.save_chat(&edited).unwrap()), (0, 1, 0));
+
+		let deleted = chat_with(&[(1, false, true, "Hi"), (2, true, true, "Hello there")]);
+		assert_eq!(counts(store.save_chat(&deleted).unwrap()), (0, 0, 1));
+
+		let stored = store.chat(1).unwrap().unwrap();
+		assert_eq!(
+			summarized(stored.chat_messages_ref()),
+			[(false, true, "Hi"), (true, true, "Hello there")]
+		);
+		assert!(store.chat(2).unwrap().is_none());
+	}
+
+	#[cfg(feature = "store")]
+	#[test]
+	fn store_search_filters_and_context() {
+		use crate::store::{SearchHit, SearchQuery, Store};
+
+		fn ids(hits: &[SearchHit]) -> Vec<u64> {
+			let mut ids: Vec<u64> = hits
+				.iter()
+				.map(|h: &SearchHit| *h.message_ref().id_ref())
+				.collect();
+			ids.sort();
+			ids
+		}
+		fn context_ids(messages: &[chat::Message]) -> Vec<u64> {
+			messages
+				.iter()
+				.map(|m: &chat::Message| *m.id_ref())
+				.collect()
+		}
+
+		let store = Store::in_memory().unwrap();
+		store
+			.save_chat(&chat_with(&[
+				(1, false, true, "We met under the old bridge"),
+				(2, true, true, "The bridge was old and under water"),
+				(3, true, false, "Dragons live under the old bridge"),
+				(4, false, true, "A dragon appeared"),
+				(5, true, true, "Dragging the dragon away"),
+				(6, false, true, "Goodbye"),
+			]))
+			.unwrap();
+		let search = |query: SearchQuery| store.search(&query).unwrap();
+
+		let phrase = SearchQuery::new().phrase("under the old bridge");
+		assert_eq!(ids(&search(phrase.clone())), [1, 3]);
+		assert_eq!(ids(&search(phrase.main_only(true))), [1]);
+
+		let prefix = SearchQuery::new().prefix("drag");
+		assert_eq!(ids(&search(prefix.clone())), [3, 4, 5]);
+		assert_eq!(ids(&search(prefix.clone().from_bot(true))), [3, 5]);
+		let since = "2024-01-01T00:04:00Z".parse().unwrap();
+		assert_eq!(ids(&search(prefix.since(since))), [4, 5]);
+
+		// context only holds main messages, the alternative 3 is left out
+		let hits = search(SearchQuery::new().words("appeared").context(1));
+		assert_eq!(ids(&hits), [4]);
+		assert_eq!(hits[0].snippet_ref(), "A dragon [appeared]");
+		assert_eq!(context_ids(hits[0].before_ref()), [2]);
+		assert_eq!(context_ids(hits[0].after_ref()), [5]);
+		let hits = search(SearchQuery::new().words("goodbye").context(2));
+		assert_eq!(context_ids(hits[0].before_ref()), [4, 5]);
+		assert!(hits[0].after_ref().is_empty());
+	}
 }
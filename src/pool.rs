@@ -0,0 +1,252 @@
+use crate::auth::AuthorizedClient;
+use crate::transport::{HttpTransport, ReqwestTransport};
+use crate::types::character::{Character, QueryResponse, SortMode};
+use crate::types::error::HampterError;
+use std::time::{Duration, Instant};
+
+/**
+ * Bearer tokens expire after 30 minutes
+ * Used to schedule refreshes for tokens whose claims can't be decoded
+ */
+const TOKEN_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SelectionStrategy {
+	RoundRobin,
+	LeastRecentlyUsed,
+}
+
+struct Account<T: HttpTransport> {
+	label: String,
+	client: AuthorizedClient<T>,
+	last_used: Option<Instant>,
+	refreshed_at: Instant,
+	quarantined: bool,
+}
+
+impl<T: HttpTransport> Account<T> {
+	/**
+	 * The moment the bearer of this account should be refreshed
+	 */
+	fn refresh_at(&self, margin: Duration) -> Instant {
+		let remaining = match self.client.time_until_expiry() {
+			Ok(delta) => delta.to_std().unwrap_or(Duration::ZERO),
+			Err(_) => TOKEN_LIFETIME.saturating_sub(self.refreshed_at.elapsed()),
+		};
+		Instant::now() + remaining.saturating_sub(margin)
+	}
+}
+
+/**
+ * A set of labeled clients for spreading read-only requests over multiple accounts
+ * Accounts whose cf_clearance got rejected are quarantined until released or replaced
+ */
+pub struct AccountPool<T: HttpTransport = ReqwestTransport> {
+	accounts: Vec<Account<T>>,
+	strategy: SelectionStrategy,
+	cursor: usize,
+	refresh_margin: Duration,
+}
+
+impl<T: HttpTransport> AccountPool<T> {
+	pub fn new(strategy: SelectionStrategy) -> Self {
+		Self {
+			accounts: vec![],
+			strategy,
+			cursor: 0,
+			refresh_margin: Duration::from_secs(5 * 60),
+		}
+	}
+
+	/**
+	 * Add an account to the pool
+	 * An existing account with the same label is replaced and leaves quarantine
+	 */
+	pub fn insert(&mut self, label: &str, client: AuthorizedClient<T>) {
+		let account = Account {
+			label: label.to_string(),
+			client,
+			last_used: None,
+			refreshed_at: Instant::now(),
+			quarantined: false,
+		};
+		match self.position(label) {
+			Some(i) => self.accounts[i] = account,
+			None => self.accounts.push(account),
+		}
+	}
+
+	pub fn remove(&mut self, label: &str) -> Option<AuthorizedClient<T>> {
+		let i = self.position(label)?;
+		Some(self.accounts.remove(i).client)
+	}
+
+	pub fn get(&self, label: &str) -> Option<&AuthorizedClient<T>> {
+		self.accounts
+			.iter()
+			.find(|a: &&Account<T>| a.label == label)
+			.map(|a: &Account<T>| &a.client)
+	}
+
+	pub fn labels(&self) -> Vec<&str> {
+		self.accounts
+			.iter()
+			.map(|a: &Account<T>| a.label.as_str())
+			.collect()
+	}
+
+	pub fn len(&self) -> usize {
+		self.accounts.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.accounts.is_empty()
+	}
+
+	/**
+	 * Pick the next usable account according to the pool's strategy
+	 * Returns `None` if every account is quarantined
+	 */
+	pub fn select(&mut self) -> Option<(&str, &AuthorizedClient<T>)> {
+		let i = self.select_index()?;
+		let account = &self.accounts[i];
+		Some((&account.label, &account.client))
+	}
+
+	pub fn quarantine(&mut self, label: &str) {
+		if let Some(i) = self.position(label) {
+			self.accounts[i].quarantined = true;
+		}
+	}
+
+	/**
+	 * Put a quarantined account back into rotation, e.g. after renewing its cf_clearance elsewhere
+	 */
+	pub fn release(&mut self, label: &str) {
+		if let Some(i) = self.position(label) {
+			self.accounts[i].quarantined = false;
+		}
+	}
+
+	pub fn is_quarantined(&self, label: &str) -> bool {
+		self.position(label)
+			.is_some_and(|i: usize| self.accounts[i].quarantined)
+	}
+
+	/**
+	 * Feed the outcome of a request made with an account back into the pool
+	 * Accounts blocked by Cloudflare are quarantined
+	 */
+	pub fn report<R>(&mut self, label: &str, result: &Result<R, HampterError>) {
+		if result
+			.as_ref()
+			.is_err_and(|e: &HampterError| e.is_cloudflare_block())
+		{
+			self.quarantine(label);
+		}
+	}
+
+	/**
+	 * How long before a bearer expires it gets refreshed, defaults to 5 minutes
+	 */
+	pub fn set_refresh_margin(&mut self, margin: Duration) {
+		self.refresh_margin = margin;
+	}
+
+	/**
+	 * The scheduled refresh of every account, soonest first
+	 */
+	pub fn refresh_schedule(&self) -> Vec<(&str, Instant)> {
+		let mut schedule: Vec<(&str, Instant)> = self
+			.accounts
+			.iter()
+			.map(|a: &Account<T>| (a.label.as_str(), a.refresh_at(self.refresh_margin)))
+			.collect();
+		schedule.sort_by_key(|(_, at)| *at);
+		schedule
+	}
+
+	/**
+	 * Refresh the bearer of every account that is due
+	 * Returns the accounts that failed to refresh, those blocked by Cloudflare are quarantined
+	 */
+	pub async fn refresh_due(&mut self) -> Vec<(String, HampterError)> {
+		let now = Instant::now();
+		let mut failed = vec![];
+		for account in self.accounts.iter_mut() {
+			if account.quarantined || account.refresh_at(self.refresh_margin) > now {
+				continue;
+			}
+			match account.client.refresh_auth_token().await {
+				Ok(()) => account.refreshed_at = Instant::now(),
+				Err(e) => {
+					account.quarantined = e.is_cloudflare_block();
+					failed.push((account.label.clone(), e));
+				}
+			}
+		}
+		failed
+	}
+
+	/**
+	 * `Character::query` using the next usable account
+	 * Falls through to the following account if Cloudflare blocks the request
+	 */
+	pub async fn query_characters(
+		&mut self,
+		page: u32,
+		nsfw: bool,
+		search: Option<&str>,
+		sort: Option<SortMode>,
+		tag_ids: Option<Vec<u32>>,
+		custom_tags: Option<Vec<&str>>,
+	) -> Result<QueryResponse, HampterError> {
+		loop {
+			let i = self
+				.select_index()
+				.ok_or(HampterError::NoAccountAvailable)?;
+			let res = Character::query(
+				&self.accounts[i].client,
+				page,
+				nsfw,
+				search,
+				sort,
+				tag_ids.clone(),
+				custom_tags.clone(),
+			)
+			.await;
+			match res {
+				Err(e) if e.is_cloudflare_block() => self.accounts[i].quarantined = true,
+				res => return res,
+			}
+		}
+	}
+
+	fn position(&self, label: &str) -> Option<usize> {
+		self.accounts
+			.iter()
+			.position(|a: &Account<T>| a.label == label)
+	}
+
+	fn select_index(&mut self) -> Option<usize> {
+		let i = match self.strategy {
+			SelectionStrategy::RoundRobin => {
+				let len = self.accounts.len();
+				let i = (0..len)
+					.map(|offset: usize| (self.cursor + offset) % len)
+					.find(|i: &usize| !self.accounts[*i].quarantined)?;
+				self.cursor = i + 1;
+				i
+			}
+			SelectionStrategy::LeastRecentlyUsed => self
+				.accounts
+				.iter()
+				.enumerate()
+				.filter(|(_, a)| !a.quarantined)
+				.min_by_key(|(_, a)| a.last_used)
+				.map(|(i, _)| i)?,
+		};
+		self.accounts[i].last_used = Some(Instant::now());
+		Some(i)
+	}
+}
@@ -0,0 +1,602 @@
+/*!
+ * Local SQLite mirror of chats, characters and personas, enabled with the `store` feature
+ * Models are kept as JSON next to the columns needed for lookups, so fields the crate doesn't
+ * model yet survive the round trip. Message texts are indexed for `Store::search`.
+ * The API has no cheaper way to tell whether a chat changed, so every sync fetches the whole chat,
+ * only the writes to SQLite are incremental.
+ */
+use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
+use crate::types::{
+	character::Character,
+	chat::{Chat, Message},
+	error::HampterError,
+	persona::Persona,
+};
+use getters2::Getters;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+/**
+ * Stored in `PRAGMA user_version`, bumped whenever the tables below change
+ */
+const SCHEMA_VERSION: i32 = 2;
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS chats (
+		id INTEGER PRIMARY KEY,
+		character_id TEXT NOT NULL,
+		updated_at TEXT NOT NULL,
+		info TEXT NOT NULL,
+		character TEXT NOT NULL
+	);
+	CREATE INDEX IF NOT EXISTS chats_character ON chats (character_id);
+	CREATE TABLE IF NOT EXISTS messages (
+		id INTEGER PRIMARY KEY,
+		chat_id INTEGER NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+		created_at TEXT NOT NULL,
+		is_bot INTEGER NOT NULL,
+		is_main INTEGER NOT NULL,
+		message TEXT NOT NULL,
+		json TEXT NOT NULL
+	);
+	CREATE INDEX IF NOT EXISTS messages_chat ON messages (chat_id, created_at);
+	CREATE TABLE IF NOT EXISTS characters (
+		id TEXT PRIMARY KEY,
+		name TEXT NOT NULL,
+		creator_id TEXT NOT NULL,
+		json TEXT NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS personas (
+		id TEXT PRIMARY KEY,
+		name TEXT NOT NULL,
+		json TEXT NOT NULL
+	);
+	CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5 (
+		message, content = 'messages', content_rowid = 'id'
+	);
+	CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+		INSERT INTO messages_fts (rowid, message) VALUES (new.id, new.message);
+	END;
+	CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+		INSERT INTO messages_fts (messages_fts, rowid, message) VALUES ('delete', old.id, old.message);
+	END;
+	CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE ON messages BEGIN
+		INSERT INTO messages_fts (messages_fts, rowid, message) VALUES ('delete', old.id, old.message);
+		INSERT INTO messages_fts (rowid, message) VALUES (new.id, new.message);
+	END;
+";
+
+/**
+ * Marks the matched words in `SearchHit::snippet`
+ */
+const HIGHLIGHT: (&str, &str) = ("[", "]");
+
+/**
+ * What a sync changed locally
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Getters)]
+pub struct SyncStats {
+	added: usize,
+	updated: usize,
+	removed: usize,
+}
+
+impl SyncStats {
+	pub fn is_empty(&self) -> bool {
+		self.added == 0 && self.updated == 0 && self.removed == 0
+	}
+}
+
+pub struct Store {
+	connection: Mutex<Connection>,
+}
+
+impl Store {
+	pub fn open(path: impl AsRef<Path>) -> Result<Store, HampterError> {
+		Self::init(Connection::open(path)?)
+	}
+
+	pub fn in_memory() -> Result<Store, HampterError> {
+		Self::init(Connection::open_in_memory()?)
+	}
+
+	fn init(connection: Connection) -> Result<Store, HampterError> {
+		connection.pragma_update(None, "foreign_keys", true)?;
+		let version: i32 = connection.pragma_query_value(None, "user_version", |r| r.get(0))?;
+		if version > SCHEMA_VERSION {
+			return Err(HampterError::InvalidInput(format!(
+				"store schema version {} is newer than the supported version {}",
+				version, SCHEMA_VERSION
+			)));
+		}
+		connection.execute_batch(SCHEMA)?;
+		if version < 2 {
+			// messages stored before the search index existed
+			connection.execute(
+				"INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')",
+				[],
+			)?;
+		}
+		connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+		Ok(Store {
+			connection: Mutex::new(connection),
+		})
+	}
+
+	/**
+	 * Fetch the whole chat and write what changed since the last sync
+	 */
+	pub async fn sync_chat<T: HttpTransport>(
+		&self,
+		id: u64,
+		client: &AuthorizedClient<T>,
+	) -> Result<SyncStats, HampterError> {
+		let chat = Chat::get(id, client).await?;
+		self.save_chat(&chat)
+	}
+
+	/**
+	 * Write a chat, adding new messages, updating edited ones and removing deleted ones
+	 */
+	pub fn save_chat(&self, chat: &Chat) -> Result<SyncStats, HampterError> {
+		let id = *chat.chat_ref().id_ref() as i64;
+		let mut connection = self.connection.lock().unwrap();
+		let tx = connection.transaction()?;
+		tx.execute(
+			"INSERT INTO chats (id, character_id, updated_at, info, character)
+			VALUES (?1, ?2, ?3, ?4, ?5)
+			ON CONFLICT (id) DO UPDATE SET
+				character_id = excluded.character_id, updated_at = excluded.updated_at,
+				info = excluded.info, character = excluded.character",
+			params![
+				id,
+				chat.chat_ref().character_id_ref(),
+				chat.chat_ref().updated_at_ref().to_rfc3339(),
+				serde_json::to_string(chat.chat_ref())?,
+				serde_json::to_string(chat.character_ref())?,
+			],
+		)?;
+
+		let mut stored: HashMap<i64, String> = HashMap::new();
+		{
+			let mut statement = tx.prepare("SELECT id, json FROM messages WHERE chat_id = ?1")?;
+			let rows = statement.query_map(params![id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+			for row in rows {
+				let (message_id, json) = row?;
+				stored.insert(message_id, json);
+			}
+		}
+
+		let mut stats = SyncStats::default();
+		for message in chat.chat_messages_ref() {
+			let json = serde_json::to_string(message)?;
+			let message_id = *message.id_ref() as i64;
+			match stored.remove(&message_id) {
+				Some(old) if old == json => continue,
+				Some(_) => stats.updated += 1,
+				None => stats.added += 1,
+			}
+			tx.execute(
+				"INSERT INTO messages (id, chat_id, created_at, is_bot, is_main, message, json)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+				ON CONFLICT (id) DO UPDATE SET
+					chat_id = excluded.chat_id, created_at = excluded.created_at,
+					is_bot = excluded.is_bot, is_main = excluded.is_main,
+					message = excluded.message, json = excluded.json",
+				params![
+					message_id,
+					id,
+					message.created_at_ref().to_rfc3339(),
+					message.is_bot_ref(),
+					message.is_main_ref(),
+					message.message_ref(),
+					json,
+				],
+			)?;
+		}
+		for message_id in stored.keys() {
+			tx.execute("DELETE FROM messages WHERE id = ?1", params![message_id])?;
+			stats.removed += 1;
+		}
+		tx.commit()?;
+		Ok(stats)
+	}
+
+	pub async fn sync_character<T: HttpTransport>(
+		&self,
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		let character = Character::get(id, client).await?;
+		self.save_character(&character)
+	}
+
+	pub fn save_character(&self, character: &Character) -> Result<(), HampterError> {
+		self.connection.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO characters (id, name, creator_id, json) VALUES (?1, ?2, ?3, ?4)",
+			params![
+				character.id_ref(),
+				character.name_ref(),
+				character.creator_id_ref(),
+				serde_json::to_string(character)?,
+			],
+		)?;
+		Ok(())
+	}
+
+	/**
+	 * Replace the stored personas with the current ones of the account
+	 */
+	pub async fn sync_personas<T: HttpTransport>(
+		&self,
+		client: &AuthorizedClient<T>,
+	) -> Result<SyncStats, HampterError> {
+		let personas = Persona::list(client).await?;
+		self.save_personas(&personas)
+	}
+
+	pub fn save_personas(&self, personas: &[Persona]) -> Result<SyncStats, HampterError> {
+		let mut connection = self.connection.lock().unwrap();
+		let tx = connection.transaction()?;
+		let mut stored: HashMap<String, String> = HashMap::new();
+		{
+			let mut statement = tx.prepare("SELECT id, json FROM personas")?;
+			let rows = statement.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+			for row in rows {
+				let (id, json) = row?;
+				stored.insert(id, json);
+			}
+		}
+		let mut stats = SyncStats::default();
+		for persona in personas {
+			let json = serde_json::to_string(persona)?;
+			match stored.remove(persona.id_ref()) {
+				Some(old) if old == json => continue,
+				Some(_) => stats.updated += 1,
+				None => stats.added += 1,
+			}
+			tx.execute(
+				"INSERT OR REPLACE INTO personas (id, name, json) VALUES (?1, ?2, ?3)",
+				params![persona.id_ref(), persona.name_ref(), json],
+			)?;
+		}
+		for id in stored.keys() {
+			tx.execute("DELETE FROM personas WHERE id = ?1", params![id])?;
+			stats.removed += 1;
+		}
+		tx.commit()?;
+		Ok(stats)
+	}
+
+	/**
+	 * When the stored copy of a chat was last updated on the server
+	 */
+	pub fn chat_updated_at(
+		&self,
+		id: u64,
+	) -> Result<Option<chrono::DateTime<chrono::Utc>>, HampterError> {
+		let updated_at: Option<String> = self
+			.connection
+			.lock()
+			.unwrap()
+			.query_row(
+				"SELECT updated_at FROM chats WHERE id = ?1",
+				params![id as i64],
+				|r| r.get(0),
+			)
+			.optional()?;
+		Ok(updated_at
+			.and_then(|u: String| chrono::DateTime::parse_from_rfc3339(&u).ok())
+			.map(|u: chrono::DateTime<chrono::FixedOffset>| u.with_timezone(&chrono::Utc)))
+	}
+
+	/**
+	 * IDs of the stored chats, optionally only those with one character, most recent first
+	 */
+	pub fn chat_ids(&self, character_id: Option<&str>) -> Result<Vec<u64>, HampterError> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare(
+			"SELECT id FROM chats WHERE ?1 IS NULL OR character_id = ?1 ORDER BY updated_at DESC",
+		)?;
+		let ids = statement
+			.query_map(params![character_id], |r| r.get::<_, i64>(0))?
+			.map(|id: rusqlite::Result<i64>| id.map(|id: i64| id as u64))
+			.collect::<rusqlite::Result<Vec<u64>>>()?;
+		Ok(ids)
+	}
+
+	/**
+	 * A stored chat with all its messages, oldest first
+	 */
+	pub fn chat(&self, id: u64) -> Result<Option<Chat>, HampterError> {
+		let row: Option<(String, String)> = self
+			.connection
+			.lock()
+			.unwrap()
+			.query_row(
+				"SELECT info, character FROM chats WHERE id = ?1",
+				params![id as i64],
+				|r| Ok((r.get(0)?, r.get(1)?)),
+			)
+			.optional()?;
+		let Some((info, character)) = row else {
+			return Ok(None);
+		};
+		let messages: Vec<Message> = self.query_json(
+			"SELECT json FROM messages WHERE chat_id = ?1 ORDER BY created_at, id",
+			params![id as i64],
+		)?;
+		Ok(Some(serde_json::from_value(serde_json::json!({
+			"chat": serde_json::from_str::<serde_json::Value>(&info)?,
+			"character": serde_json::from_str::<serde_json::Value>(&character)?,
+			"chatMessages": messages,
+		}))?))
+	}
+
+	/**
+	 * The messages of a stored chat, oldest first
+	 */
+	pub fn messages(&self, chat_id: u64, main_only: bool) -> Result<Vec<Message>, HampterError> {
+		self.query_json(
+			"SELECT json FROM messages WHERE chat_id = ?1 AND (is_main OR NOT ?2)
+			ORDER BY created_at, id",
+			params![chat_id as i64, main_only],
+		)
+	}
+
+	pub fn character(&self, id: &str) -> Result<Option<Character>, HampterError> {
+		let json: Option<String> = self
+			.connection
+			.lock()
+			.unwrap()
+			.query_row(
+				"SELECT json FROM characters WHERE id = ?1",
+				params![id],
+				|r| r.get(0),
+			)
+			.optional()?;
+		Ok(json.map(|j: String| serde_json::from_str(&j)).transpose()?)
+	}
+
+	pub fn characters(&self) -> Result<Vec<Character>, HampterError> {
+		self.query_json("SELECT json FROM characters ORDER BY name", [])
+	}
+
+	pub fn personas(&self) -> Result<Vec<Persona>, HampterError> {
+		self.query_json("SELECT json FROM personas ORDER BY name", [])
+	}
+
+	/**
+	 * Search the text of all stored messages, best matches first
+	 */
+	pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, HampterError> {
+		let expression = query.expression();
+		if expression.is_empty() {
+			return Err(HampterError::InvalidInput(
+				"a search needs at least one word, phrase or prefix".to_string(),
+			));
+		}
+		let rows: Vec<(i64, String, String, String)> = {
+			let connection = self.connection.lock().unwrap();
+			let mut statement = connection.prepare(
+				"SELECT m.chat_id, c.character_id, m.json,
+					snippet(messages_fts, 0, ?8, ?9, '…', ?10)
+				FROM messages_fts
+				JOIN messages m ON m.id = messages_fts.rowid
+				JOIN chats c ON c.id = m.chat_id
+				WHERE messages_fts MATCH ?1
+					AND (?2 IS NULL OR c.character_id = ?2)
+					AND (?3 IS NULL OR m.created_at >= ?3)
+					AND (?4 IS NULL OR m.created_at <= ?4)
+					AND (?5 IS NULL OR m.is_bot = ?5)
+					AND (m.is_main OR NOT ?6)
+				ORDER BY bm25(messages_fts)
+				LIMIT ?7",
+			)?;
+			let rows = statement
+				.query_map(
+					params![
+						expression,
+						query.character_id,
+						query
+							.since
+							.map(|d: chrono::DateTime<chrono::Utc>| d.to_rfc3339()),
+						query
+							.until
+							.map(|d: chrono::DateTime<chrono::Utc>| d.to_rfc3339()),
+						query.from_bot,
+						query.main_only,
+						query.limit,
+						HIGHLIGHT.0,
+						HIGHLIGHT.1,
+						query.snippet_words,
+					],
+					|r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+				)?
+				.collect::<rusqlite::Result<Vec<(i64, String, String, String)>>>()?;
+			rows
+		};
+
+		let mut hits = vec![];
+		for (chat_id, character_id, json, snippet) in rows {
+			let message: Message = serde_json::from_str(&json)?;
+			let position = params![
+				chat_id,
+				message.created_at_ref().to_rfc3339(),
+				*message.id_ref() as i64,
+				query.context,
+			];
+			let mut before: Vec<Message> = self.query_json(
+				"SELECT json FROM messages
+				WHERE chat_id = ?1 AND is_main AND (created_at, id) < (?2, ?3)
+				ORDER BY created_at DESC, id DESC LIMIT ?4",
+				position,
+			)?;
+			before.reverse();
+			let after: Vec<Message> = self.query_json(
+				"SELECT json FROM messages
+				WHERE chat_id = ?1 AND is_main AND (created_at, id) > (?2, ?3)
+				ORDER BY created_at, id LIMIT ?4",
+				position,
+			)?;
+			hits.push(SearchHit {
+				chat_id: chat_id as u64,
+				character_id,
+				message,
+				snippet,
+				before,
+				after,
+			});
+		}
+		Ok(hits)
+	}
+
+	fn query_json<V: serde::de::DeserializeOwned>(
+		&self,
+		sql: &str,
+		params: impl rusqlite::Params,
+	) -> Result<Vec<V>, HampterError> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare(sql)?;
+		let rows = statement
+			.query_map(params, |r| r.get::<_, String>(0))?
+			.collect::<rusqlite::Result<Vec<String>>>()?;
+		Ok(rows
+			.iter()
+			.map(|json: &String| serde_json::from_str(json))
+			.collect::<Result<Vec<V>, serde_json::Error>>()?)
+	}
+}
+
+/**
+ * A search over the stored messages, every word, phrase and prefix has to match
+ */
+#[derive(Clone)]
+pub struct SearchQuery {
+	terms: Vec<String>, // FTS5 expressions, already quoted
+	character_id: Option<String>,
+	since: Option<chrono::DateTime<chrono::Utc>>,
+	until: Option<chrono::DateTime<chrono::Utc>>,
+	from_bot: Option<bool>,
+	main_only: bool,
+	context: u32,
+	limit: u32,
+	snippet_words: u32,
+}
+
+impl Default for SearchQuery {
+	fn default() -> Self {
+		Self {
+			terms: vec![],
+			character_id: None,
+			since: None,
+			until: None,
+			from_bot: None,
+			main_only: false,
+			context: 2,
+			limit: 20,
+			snippet_words: 16,
+		}
+	}
+}
+
+impl SearchQuery {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/**
+	 * Each word has to appear somewhere in the message
+	 */
+	pub fn words(mut self, words: &str) -> Self {
+		self.terms
+			.extend(words.split_whitespace().map(|w: &str| quote(w)));
+		self
+	}
+
+	/**
+	 * The words have to appear in this order, next to each other
+	 */
+	pub fn phrase(mut self, phrase: &str) -> Self {
+		if !phrase.trim().is_empty() {
+			self.terms.push(quote(phrase.trim()));
+		}
+		self
+	}
+
+	/**
+	 * Some word has to start with `prefix`, e.g. "drag" finds "dragon" and "dragging"
+	 */
+	pub fn prefix(mut self, prefix: &str) -> Self {
+		if !prefix.trim().is_empty() {
+			self.terms.push(format!("{} *", quote(prefix.trim())));
+		}
+		self
+	}
+
+	pub fn character(mut self, character_id: &str) -> Self {
+		self.character_id = Some(character_id.to_string());
+		self
+	}
+
+	pub fn since(mut self, since: chrono::DateTime<chrono::Utc>) -> Self {
+		self.since = Some(since);
+		self
+	}
+
+	pub fn until(mut self, until: chrono::DateTime<chrono::Utc>) -> Self {
+		self.until = Some(until);
+		self
+	}
+
+	/**
+	 * Only messages of the bot (`true`) or of the user (`false`)
+	 */
+	pub fn from_bot(mut self, from_bot: bool) -> Self {
+		self.from_bot = Some(from_bot);
+		self
+	}
+
+	/**
+	 * Skip alternative generations that were swiped away
+	 */
+	pub fn main_only(mut self, main_only: bool) -> Self {
+		self.main_only = main_only;
+		self
+	}
+
+	/**
+	 * How many messages before and after a hit to include, 2 by default
+	 */
+	pub fn context(mut self, context: u32) -> Self {
+		self.context = context;
+		self
+	}
+
+	pub fn limit(mut self, limit: u32) -> Self {
+		self.limit = limit;
+		self
+	}
+
+	fn expression(&self) -> String {
+		self.terms.join(" AND ")
+	}
+}
+
+#[derive(Clone, Getters)]
+pub struct SearchHit {
+	chat_id: u64,
+	character_id: String,
+	message: Message,
+	snippet: String, // the matching part of the message with the matches in brackets
+	before: Vec<Message>,
+	after: Vec<Message>,
+}
+
+/**
+ * A string literal in an FTS5 query, so user input can't use the query syntax
+ */
+fn quote(text: &str) -> String {
+	format!("\"{}\"", text.replace('"', "\"\""))
+}
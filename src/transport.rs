@@ -0,0 +1,196 @@
+use crate::types::error::HampterError;
+use futures::{Stream, TryStreamExt};
+use reqwest::{
+	header::{HeaderMap, HeaderValue, IntoHeaderName},
+	Method, StatusCode,
+};
+use std::{future::Future, io, pin::Pin};
+use tokio_util::bytes::Bytes;
+
+/**
+ * A response body that is read chunk by chunk, needed for the generation stream
+ */
+pub type BodyStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/**
+ * A request as handed to a transport
+ * The authorization headers are already attached by the client
+ */
+pub struct HttpRequest {
+	method: Method,
+	url: String,
+	headers: HeaderMap,
+	body: Option<Vec<u8>>,
+}
+
+impl HttpRequest {
+	pub fn new(method: Method, url: impl Into<String>) -> Self {
+		Self {
+			method,
+			url: url.into(),
+			headers: HeaderMap::new(),
+			body: None,
+		}
+	}
+
+	pub fn header(mut self, name: impl IntoHeaderName, value: &str) -> Result<Self, HampterError> {
+		self.headers.insert(name, HeaderValue::from_str(value)?);
+		Ok(self)
+	}
+
+	/**
+	 * Serialize `body` as the JSON request body
+	 */
+	pub fn json<B: serde::Serialize + ?Sized>(mut self, body: &B) -> Result<Self, HampterError> {
+		self.body = Some(serde_json::to_vec(body)?);
+		self.headers.insert(
+			reqwest::header::CONTENT_TYPE,
+			HeaderValue::from_static("application/json"),
+		);
+		Ok(self)
+	}
+
+	pub fn method(&self) -> &Method {
+		&self.method
+	}
+
+	pub fn url(&self) -> &str {
+		&self.url
+	}
+
+	pub fn headers(&self) -> &HeaderMap {
+		&self.headers
+	}
+
+	pub fn body(&self) -> Option<&[u8]> {
+		self.body.as_deref()
+	}
+
+	pub fn into_parts(self) -> (Method, String, HeaderMap, Option<Vec<u8>>) {
+		(self.method, self.url, self.headers, self.body)
+	}
+}
+
+pub struct HttpResponse {
+	status: StatusCode,
+	headers: HeaderMap,
+	body: BodyStream,
+}
+
+impl HttpResponse {
+	pub fn new(status: StatusCode, headers: HeaderMap, body: BodyStream) -> Self {
+		Self {
+			status,
+			headers,
+			body,
+		}
+	}
+
+	/**
+	 * A response with a body that is already completely in memory
+	 */
+	pub fn from_bytes(status: StatusCode, headers: HeaderMap, body: impl Into<Bytes>) -> Self {
+		let body: Bytes = body.into();
+		Self::new(
+			status,
+			headers,
+			Box::pin(futures::stream::once(async move { Ok(body) })),
+		)
+	}
+
+	pub fn status(&self) -> StatusCode {
+		self.status
+	}
+
+	pub fn headers(&self) -> &HeaderMap {
+		&self.headers
+	}
+
+	pub fn error_for_status(self) -> Result<Self, HampterError> {
+		if self.status.is_client_error() || self.status.is_server_error() {
+			return Err(HampterError::Status(self.status, self.headers));
+		}
+		Ok(self)
+	}
+
+	pub fn bytes_stream(self) -> BodyStream {
+		self.body
+	}
+
+	pub async fn bytes(self) -> Result<Vec<u8>, HampterError> {
+		Ok(self
+			.body
+			.try_fold(vec![], |mut buf: Vec<u8>, chunk: Bytes| async move {
+				buf.extend_from_slice(&chunk);
+				Ok(buf)
+			})
+			.await?)
+	}
+
+	/**
+	 * Deserialize the body, errors name the path of the offending field
+	 */
+	pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, HampterError> {
+		let bytes = self.bytes().await?;
+		let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+		crate::types::schema::in_response(|| serde_path_to_error::deserialize(deserializer))
+			.map_err(|e| {
+				let path = e.path().to_string();
+				let source = e.into_inner();
+				if crate::types::schema::mode() != crate::types::schema::SchemaMode::Off {
+					log::warn!("failed to deserialize `{}`: {}", path, source);
+				}
+				HampterError::SchemaMismatch { path, source }
+			})
+	}
+}
+
+/**
+ * Sends requests on behalf of an `AuthorizedClient`
+ * Implement this to swap the HTTP backend, e.g. for an in-memory fake in tests
+ */
+pub trait HttpTransport: Send + Sync {
+	fn execute(
+		&self,
+		request: HttpRequest,
+	) -> impl Future<Output = Result<HttpResponse, HampterError>> + Send;
+}
+
+/**
+ * The default transport backed by reqwest
+ */
+pub struct ReqwestTransport {
+	client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+	pub fn new() -> Result<Self, HampterError> {
+		Ok(Self::from_client(
+			reqwest::ClientBuilder::new().cookie_store(true).build()?,
+		))
+	}
+
+	pub fn from_client(client: reqwest::Client) -> Self {
+		Self { client }
+	}
+
+	pub fn client(&self) -> &reqwest::Client {
+		&self.client
+	}
+}
+
+impl HttpTransport for ReqwestTransport {
+	async fn execute(&self, request: HttpRequest) -> Result<HttpResponse, HampterError> {
+		let (method, url, headers, body) = request.into_parts();
+		let mut builder = self.client.request(method, url).headers(headers);
+		if let Some(body) = body {
+			builder = builder.body(body);
+		}
+		let res = builder.send().await?;
+		Ok(HttpResponse::new(
+			res.status(),
+			res.headers().clone(),
+			Box::pin(res.bytes_stream().map_err(io::Error::other)),
+		))
+	}
+}
@@ -1,5 +1,7 @@
-use super::{error::HampterError, misc, tag::Tag};
+use super::{error::HampterError, misc, schema, tag::Tag};
 use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
+use futures::Stream;
 use getters2::Getters;
 use serde;
 
@@ -7,7 +9,11 @@ use serde;
 pub struct CharacterStats {
 	chat: u64,
 	message: u64,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<CharacterStats, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(CharacterStats, ["chat", "message"]);
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct TokenStats {
@@ -16,7 +22,14 @@ pub struct TokenStats {
 	first_message_tokens: u32,
 	example_dialog_tokens: u32,
 	total_tokens: u32,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<TokenStats, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(TokenStats, [
+	"scenario_tokens", "personality_tokens", "first_message_tokens", "example_dialog_tokens",
+	"total_tokens",
+]);
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Character {
@@ -46,7 +59,17 @@ pub struct Character {
 	is_image_nsfw: Option<bool>,
 	tags: Vec<Tag>,
 	stats: CharacterStats,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Character, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Character, [
+	"id", "name", "avatar", "description", "chat_name", "created_at", "updated_at",
+	"first_published_at", "is_public", "is_force_remove", "is_deleted", "showdefinition",
+	"showDefinitionOverride", "allow_proxy", "is_nsfw", "creator_id", "creator_name",
+	"creator_verified", "custom_tags", "soundcloud_track_id", "token_counts", "is_image_nsfw",
+	"tags", "stats",
+]);
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct QueryResponse {
@@ -56,8 +79,26 @@ pub struct QueryResponse {
 	#[serde(deserialize_with = "misc::u64_from_string")]
 	page: u64, // original is string
 	top_custom_tags: Vec<String>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<QueryResponse, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(QueryResponse, ["data", "total", "size", "page", "top_custom_tags"]);
 
+impl QueryResponse {
+	/**
+	 * Keep only the characters `f` returns true for, e.g. with `BlockList::is_blocked`
+	 */
+	pub fn retain(&mut self, f: impl FnMut(&Character) -> bool) {
+		self.data.retain(f);
+	}
+
+	pub fn into_data(self) -> Vec<Character> {
+		self.data
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SortMode {
 	Popular,
 	Latest,
@@ -65,34 +106,31 @@ pub enum SortMode {
 	Trending24,
 	Relevance,
 }
-impl ToString for SortMode {
-	fn to_string(&self) -> String {
-		match self {
-			Self::Popular => "popular".to_string(),
-			Self::Latest => "latest".to_string(),
-			Self::Trending => "trending".to_string(),
-			Self::Trending24 => "trending24".to_string(),
-			Self::Relevance => "relevance".to_string(),
-		}
+impl std::fmt::Display for SortMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Popular => "popular",
+			Self::Latest => "latest",
+			Self::Trending => "trending",
+			Self::Trending24 => "trending24",
+			Self::Relevance => "relevance",
+		})
 	}
 }
 
 impl Character {
-	pub async fn get(
+	pub async fn get<T: HttpTransport>(
 		id: &str,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<Character, HampterError> {
-		Ok(client
-			.client()
-			.get(format!("https://janitorai.com/hampter/characters/{}", id))
-			.send()
+		client
+			.send(client.get(format!("https://janitorai.com/hampter/characters/{}", id))?)
 			.await?
-			.error_for_status()?
 			.json::<Character>()
-			.await?)
+			.await
 	}
-	pub async fn query(
-		client: &AuthorizedClient,
+	pub async fn query<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
 		page: u32,
 		nsfw: bool,
 		search: Option<&str>,
@@ -102,7 +140,7 @@ impl Character {
 	) -> Result<QueryResponse, HampterError> {
 		let mut query_string: String = format!(
 			"https://janitorai.com/hampter/characters?page={page}&mode={mode}&sort={sort}",
-			sort = sort.get_or_insert(SortMode::Popular).to_string(),
+			sort = sort.get_or_insert(SortMode::Popular),
 			mode = if nsfw { "all" } else { "sfw" }
 		);
 		for t in tag_ids.get_or_insert(vec![]) {
@@ -111,17 +149,64 @@ impl Character {
 		for t in custom_tags.get_or_insert(vec![]) {
 			query_string.push_str(format!("&custom_tags[]={}", t).as_str());
 		}
-		if search.is_some() {
-			query_string.push_str(format!("&search={}", search.unwrap()).as_str());
+		if let Some(search) = search {
+			query_string.push_str(format!("&search={}", search).as_str());
 		}
 
-		Ok(client
-			.client()
-			.get(query_string)
-			.send()
+		client
+			.send(client.get(query_string)?)
 			.await?
-			.error_for_status()?
 			.json::<QueryResponse>()
-			.await?)
+			.await
+	}
+
+	/**
+	 * Every character matching a query, the arguments are those of `query` without the page
+	 */
+	pub fn search_stream<'a, T: HttpTransport>(
+		client: &'a AuthorizedClient<T>,
+		nsfw: bool,
+		search: Option<&'a str>,
+		sort: Option<SortMode>,
+		tag_ids: Option<Vec<u32>>,
+		custom_tags: Option<Vec<&'a str>>,
+	) -> impl Stream<Item = Result<Character, HampterError>> + 'a {
+		misc::paged(move |page: u32| {
+			let tag_ids = tag_ids.clone();
+			let custom_tags = custom_tags.clone();
+			async move {
+				let res =
+					Self::query(client, page, nsfw, search, sort, tag_ids, custom_tags).await?;
+				let total = res.total;
+				Ok((res.into_data(), total))
+			}
+		})
+	}
+
+	/**
+	 * Official tags matching one of `tags` by ID, name or slug, `tags` have to be lowercase
+	 */
+	pub(crate) fn tags_matching<'a>(
+		&'a self,
+		tags: &'a [String],
+	) -> impl Iterator<Item = &'a Tag> + 'a {
+		self.tags.iter().filter(|t: &&Tag| {
+			tags.contains(&t.id_ref().to_string())
+				|| tags.contains(&t.name_ref().to_lowercase())
+				|| tags.contains(&t.slug_ref().to_lowercase())
+		})
+	}
+
+	/**
+	 * Custom tags matching one of `tags` case-insensitively, `tags` have to be lowercase
+	 */
+	pub(crate) fn custom_tags_matching<'a>(
+		&'a self,
+		tags: &'a [String],
+	) -> impl Iterator<Item = &'a String> + 'a {
+		self.custom_tags
+			.iter()
+			.flatten()
+			.filter(|t: &&String| tags.contains(&t.to_lowercase()))
 	}
 }
@@ -1,17 +1,19 @@
 use super::error::HampterError;
-use super::{misc, profile::Profile};
+use super::{misc, profile::{Config, Profile}, schema, secret};
 use crate::auth::AuthorizedClient;
+use crate::transport::{BodyStream, HttpTransport};
 use futures::io::BufReader;
-use futures::stream::{self};
+use futures::stream::{self, TryStreamExt};
 use futures::AsyncBufReadExt;
-use futures::{
-	stream::{MapErr, TryStreamExt},
-	Stream,
-};
 use getters2::Getters;
 use serde_json::json;
 use std::io;
 
+/**
+ * The lines of a generation response, parse them with `MessageChunk::from_line`
+ */
+pub type GenerationLines = futures::io::Lines<BufReader<stream::IntoAsyncRead<BodyStream>>>;
+
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Character {
 	id: String,
@@ -24,7 +26,14 @@ pub struct Character {
 	is_image_nsfw: bool,
 	allow_proxy: bool,
 	soundcloud_track_id: Option<String>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Character, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Character, [
+	"id", "name", "chat_name", "description", "avatar", "is_nsfw", "is_public", "is_image_nsfw",
+	"allow_proxy", "soundcloud_track_id",
+]);
 
 #[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
 pub struct Message {
@@ -35,7 +44,13 @@ pub struct Message {
 	chat_id: u64,
 	message: String,
 	rating: Option<f32>, // I assume it's some kind of number not too sure though
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Message, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Message, [
+	"id", "created_at", "is_bot", "is_main", "chat_id", "message", "rating",
+]);
 
 impl Message {
 	pub fn new(
@@ -49,20 +64,30 @@ impl Message {
 		Message {
 			id: *id.get_or_insert(0),
 			created_at: chrono::Utc::now(),
-			is_bot: is_bot,
-			is_main: is_main,
-			chat_id: chat_id,
+			is_bot,
+			is_main,
+			chat_id,
 			message: content.to_string(),
-			rating: rating,
+			rating,
+			extra: serde_json::Map::new(),
 		}
 	}
+
+	pub(crate) fn with_created_at(mut self, created_at: chrono::DateTime<chrono::Utc>) -> Self {
+		self.created_at = created_at;
+		self
+	}
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct TextDelta {
 	role: Option<String>,
 	content: String,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<TextDelta, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(TextDelta, ["role", "content"]);
 
 #[derive(Getters, serde::Deserialize, serde::Serialize)]
 pub struct TextChoice {
@@ -70,7 +95,11 @@ pub struct TextChoice {
 	delta: TextDelta,
 	logprobs: Option<String>,
 	finish_reason: Option<String>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<TextChoice, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(TextChoice, ["index", "delta", "logprobs", "finish_reason"]);
 
 #[derive(Getters, serde::Deserialize, serde::Serialize)]
 pub struct MessageChunk {
@@ -79,14 +108,18 @@ pub struct MessageChunk {
 	created: u64,
 	model: String,
 	choices: Vec<TextChoice>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<MessageChunk, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(MessageChunk, ["id", "object", "created", "model", "choices"]);
 
 impl MessageChunk {
 	pub fn from_line(line: &String) -> Result<Option<MessageChunk>, serde_json::Error> {
 		if line.is_empty() || line.to_lowercase().contains("data: [done]") {
 			return Ok(None);
 		}
-		let parsed = serde_json::from_str(&line.to_string().split_off(6));
+		let parsed = schema::in_response(|| serde_json::from_str(&line.to_string().split_off(6)));
 		match parsed {
 			Ok(val) => Ok(Some(val)),
 			Err(e) => Err(e),
@@ -115,7 +148,14 @@ pub struct ChatInfo {
 	user_id: String,
 	character_id: String,
 	persona_id: Option<String>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<ChatInfo, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(ChatInfo, [
+	"id", "is_public", "summary", "summary_chat_id", "chat_count", "updated_at", "user_id",
+	"character_id", "persona_id",
+]);
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Chat {
@@ -123,37 +163,40 @@ pub struct Chat {
 	character: Character,
 	#[serde(rename = "chatMessages")]
 	chat_messages: Vec<Message>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Chat, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Chat, ["chat", "character", "chatMessages"]);
 
 impl Chat {
 	/**
 	 * Chat actions
 	 */
-	pub async fn get(id: u64, client: &AuthorizedClient) -> Result<Chat, HampterError> {
-		Ok(client
-			.client()
-			.get(format!("https://janitorai.com/hampter/chats/{}", id))
-			.send()
+	pub async fn get<T: HttpTransport>(
+		id: u64,
+		client: &AuthorizedClient<T>,
+	) -> Result<Chat, HampterError> {
+		client
+			.send(client.get(format!("https://janitorai.com/hampter/chats/{}", id))?)
 			.await?
-			.error_for_status()?
 			.json::<Chat>()
-			.await?)
+			.await
 	}
 
-	pub async fn delete(id: u64, client: &AuthorizedClient) -> Result<(), HampterError> {
+	pub async fn delete<T: HttpTransport>(
+		id: u64,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
 		client
-			.client()
-			.delete(format!("https://janitorai.com/hampter/chats/{id}", id = id))
-			.send()
-			.await?
-			.error_for_status()?;
+			.send(client.delete(format!("https://janitorai.com/hampter/chats/{id}", id = id))?)
+			.await?;
 		Ok(())
 	}
 
-	#[must_use]
-	pub async fn create(
+	pub async fn create<T: HttpTransport>(
 		character_id: &str,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<Chat, HampterError> {
 		/**
 		 * Open a new chat with a character
@@ -173,17 +216,60 @@ impl Chat {
 		}
 
 		let res = client
-			.client()
-			.post("https://janitorai.com/hampter/chats")
-			.json(&json!({
-				"character_id": character_id
-			}))
-			.send()
+			.send(
+				client
+					.post("https://janitorai.com/hampter/chats")?
+					.json(&json!({
+						"character_id": character_id
+					}))?,
+			)
 			.await?
-			.error_for_status()?
 			.json::<CreateChatResponse>()
 			.await?;
-		return Ok(Self::get(res.id, client).await?);
+		Self::get(res.id, client).await
+	}
+
+	/**
+	 * Send `messages` into a new chat with `character_id`, oldest first
+	 * If the new chat opens with the character's greeting, the first bot message replaces its
+	 * text instead of being sent a second time.
+	 */
+	pub(crate) async fn replay<T: HttpTransport>(
+		character_id: &str,
+		mut messages: Vec<Message>,
+		client: &AuthorizedClient<T>,
+	) -> Result<Chat, HampterError> {
+		let mut chat = Self::create(character_id, client).await?;
+		let chat_id = chat.chat.id as u64;
+		messages.sort_by_key(|m: &Message| m.created_at);
+		let mut messages = messages.into_iter().peekable();
+
+		let greeting = chat
+			.chat_messages
+			.iter()
+			.find(|m: &&Message| m.is_bot && m.is_main)
+			.map(|m: &Message| (m.id, m.message.clone()));
+		if let Some((greeting_id, greeting)) = greeting {
+			if let Some(first) = messages.next_if(|m: &Message| m.is_bot && m.is_main) {
+				if first.message != greeting {
+					chat.edit_message(greeting_id, &first.message, client).await?;
+				}
+			}
+		}
+
+		for message in messages {
+			let message = Message::new(
+				None,
+				message.is_bot,
+				message.is_main,
+				chat_id,
+				&message.message,
+				message.rating,
+			)
+			.with_created_at(message.created_at);
+			chat.send_message(message, client).await?;
+		}
+		Self::get(chat_id, client).await
 	}
 }
 
@@ -195,15 +281,15 @@ pub enum GenerationMode {
 	SummaryLast,
 	Alternative,
 }
-impl ToString for GenerationMode {
-	fn to_string(&self) -> String {
-		match self {
-			GenerationMode::New => "NEW".to_string(),
-			GenerationMode::Alternative => "ALTERNATIVE".to_string(),
-			GenerationMode::Suggestion => "SUGGESTION".to_string(),
-			GenerationMode::SummaryFull => "SUMMARY_FULL".to_string(),
-			GenerationMode::SummaryLast => "SUMMARY_LAST".to_string(),
-		}
+impl std::fmt::Display for GenerationMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			GenerationMode::New => "NEW",
+			GenerationMode::Alternative => "ALTERNATIVE",
+			GenerationMode::Suggestion => "SUGGESTION",
+			GenerationMode::SummaryFull => "SUMMARY_FULL",
+			GenerationMode::SummaryLast => "SUMMARY_LAST",
+		})
 	}
 }
 
@@ -211,25 +297,29 @@ impl Chat {
 	/**
 	 * Chat message actions
 	 */
-	pub async fn generate(
+	pub async fn generate<T: HttpTransport>(
 		&self,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 		profile: &Profile,
+		mode: Option<GenerationMode>,
+		message: Option<Message>,
+	) -> Result<GenerationLines, HampterError> {
+		self.generate_with_config(client, profile, profile.config_ref(), mode, message)
+			.await
+	}
+
+	/**
+	 * Generate using `config` instead of the config saved in the profile
+	 * Use it to override e.g. the temperature for a single generation
+	 */
+	pub async fn generate_with_config<T: HttpTransport>(
+		&self,
+		client: &AuthorizedClient<T>,
+		profile: &Profile,
+		config: &Config,
 		mut mode: Option<GenerationMode>,
 		message: Option<Message>,
-	) -> Result<
-		futures::io::Lines<
-			BufReader<
-				stream::IntoAsyncRead<
-					MapErr<
-						impl Stream<Item = Result<tokio_util::bytes::Bytes, reqwest::Error>>,
-						impl FnMut(reqwest::Error) -> io::Error,
-					>,
-				>,
-			>,
-		>,
-		HampterError,
-	> {
+	) -> Result<GenerationLines, HampterError> {
 		let mode = mode.get_or_insert(GenerationMode::New);
 		if *mode == GenerationMode::Suggestion
 			&& message
@@ -238,12 +328,10 @@ impl Chat {
 		{
 			io::Error::new(io::ErrorKind::InvalidInput, "error".to_string()); //Err("Missing message to use auto complete".to_string())
 		}
-		let response: reqwest::Response = client
-			.client()
-			.post("https://janitorai.com/generateAlpha")
-			.json(&json!({
+		let request = secret::exposed(|| {
+			client.post("https://janitorai.com/generateAlpha")?.json(&json!({
 				"generateMode": mode.to_string(),
-				"userConfig": profile.config_ref(),
+				"userConfig": config,
 				"profile": {
 					"id": profile.id_ref(),
 					"name": profile.name_ref(),
@@ -264,27 +352,24 @@ impl Chat {
 					"profile": false,
 				}
 			}))
-			.header(reqwest::header::ORIGIN, "https://janitorai.com")
-			.send()
-			.await?
-			.error_for_status()?;
+		})?
+		.header(reqwest::header::ORIGIN, "https://janitorai.com")?;
 
-		let reader = response
-			.bytes_stream()
-			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-			.into_async_read();
+		let reader = client.send(request).await?.bytes_stream().into_async_read();
 		let decoder = BufReader::new(reader);
 		Ok(decoder.lines())
 	}
 
-	pub async fn send_message(
+	pub async fn send_message<T: HttpTransport>(
 		&mut self,
 		message: Message,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<Message, HampterError> {
-		Ok(client
-			.client()
-			.post("https://janitorai.com/hampter/chats/615543871/messages")
+		let request = client
+			.post(format!(
+				"https://janitorai.com/hampter/chats/{}/messages",
+				self.chat.id
+			))?
 			.json(&json!({
 				"id": message.id,
 				"created_at": message.created_at,
@@ -293,38 +378,40 @@ impl Chat {
 				"chat_id": message.chat_id,
 				"message": message.message,
 				"rating": message.rating
-			}))
-			.send()
+			}))?;
+		let message = client
+			.send(request)
 			.await?
-			.error_for_status()?
 			.json::<Vec<Message>>()
 			.await?
-			.get(0)
-			.expect("Response was empty")
-			.clone())
+			.into_iter()
+			.next()
+			.ok_or_else(|| {
+				HampterError::NotApplied("the server returned no message".to_string())
+			})?;
+		self.chat_messages.push(message.clone());
+		Ok(message)
 	}
 
-	pub async fn edit_message(
+	pub async fn edit_message<T: HttpTransport>(
 		&mut self,
 		message_id: u64,
 		content: &str,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<(), HampterError> {
 		client
-			.client()
-			.patch(
-				format!(
-					"https://janitorai.com/hampter/chats/{chat}/messages/{message}",
-					chat = self.chat.id,
-					message = message_id
-				)
-				.to_string(),
+			.send(
+				client
+					.patch(format!(
+						"https://janitorai.com/hampter/chats/{chat}/messages/{message}",
+						chat = self.chat.id,
+						message = message_id
+					))?
+					.json(&json!({
+						"is_main": true, // so far always has been true in the originals
+						"message": content
+					}))?,
 			)
-			.json(&json!({
-				"is_main": true, // so far always has been true in the originals
-				"message": content
-			}))
-			.send()
 			.await?;
 		self.chat_messages
 			.iter_mut()
@@ -340,24 +427,22 @@ impl Chat {
 			.cloned()
 	}
 
-	pub async fn delete_messages(
+	pub async fn delete_messages<T: HttpTransport>(
 		&mut self,
 		message_ids: Vec<u64>,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<(), HampterError> {
 		client
-			.client()
-			.delete(
-				format!(
-					"https://janitorai.com/hampter/chats/{chat}/messages",
-					chat = self.chat.id
-				)
-				.to_string(),
+			.send(
+				client
+					.delete(format!(
+						"https://janitorai.com/hampter/chats/{chat}/messages",
+						chat = self.chat.id
+					))?
+					.json(&json!({
+						"message_ids": message_ids
+					}))?,
 			)
-			.json(&json!({
-				"message_ids": message_ids
-			}))
-			.send()
 			.await?;
 		for m_id in message_ids {
 			self.chat_messages.remove(
@@ -8,4 +8,54 @@ pub enum HampterError {
 	InvalidHeader (#[from] reqwest::header::InvalidHeaderValue),
 	#[error("serialization failed")]
 	FailedSerialization (#[from] serde_json::Error),
+	#[error("malformed auth token")]
+	InvalidToken,
+	#[error("no usable account left in the pool")]
+	NoAccountAvailable,
+	#[error("io error")]
+	Io(#[from] std::io::Error),
+	#[error("server responded with {0}")]
+	Status(reqwest::StatusCode, reqwest::header::HeaderMap),
+	#[error("no recorded interaction for {0}")]
+	NotRecorded(String),
+	#[error("invalid input: {0}")]
+	InvalidInput(String),
+	#[error("change not applied: {0}")]
+	NotApplied(String),
+	#[error("unexpected value at `{path}`: {source}")]
+	SchemaMismatch {
+		path: String,
+		source: serde_json::Error,
+	},
+	#[cfg(feature = "store")]
+	#[error("local store failed")]
+	Store(#[from] rusqlite::Error),
+}
+
+impl HampterError {
+	/**
+	 * The HTTP status of a failed request, if the server answered at all
+	 */
+	pub fn status(&self) -> Option<reqwest::StatusCode> {
+		match self {
+			Self::InvalidResponse(e) => e.status(),
+			Self::Status(status, _) => Some(*status),
+			_ => None,
+		}
+	}
+
+	/**
+	 * Cloudflare answers requests with a rejected cf_clearance cookie with a challenge page,
+	 * marked by the `cf-mitigated` header. A plain 403 from the API is a missing permission.
+	 */
+	pub fn is_cloudflare_block(&self) -> bool {
+		match self {
+			Self::Status(_, headers) => headers
+				.get("cf-mitigated")
+				.is_some_and(|v: &reqwest::header::HeaderValue| {
+					v.as_bytes().eq_ignore_ascii_case(b"challenge")
+				}),
+			_ => false,
+		}
+	}
 }
\ No newline at end of file
@@ -0,0 +1,168 @@
+use super::chat::{Chat, Message};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+	Markdown,
+	Html, // a standalone page with inline styles
+	PlainText,
+}
+
+/**
+ * What goes into a transcript made by `Chat::export`
+ */
+#[derive(Clone)]
+pub struct ExportOptions {
+	user_name: String,
+	main_only: bool,
+	timestamp_format: Option<String>,
+}
+
+impl Default for ExportOptions {
+	fn default() -> Self {
+		Self {
+			user_name: "You".to_string(),
+			main_only: false,
+			timestamp_format: Some("%Y-%m-%d %H:%M".to_string()),
+		}
+	}
+}
+
+impl ExportOptions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/**
+	 * The name shown for the user's messages, e.g. `Profile::name`
+	 */
+	pub fn user_name(mut self, user_name: &str) -> Self {
+		self.user_name = user_name.to_string();
+		self
+	}
+
+	/**
+	 * Leave out the alternative generations the user swiped away
+	 */
+	pub fn main_only(mut self, main_only: bool) -> Self {
+		self.main_only = main_only;
+		self
+	}
+
+	/**
+	 * A chrono format string for `Message::created_at`, `None` leaves out timestamps
+	 */
+	pub fn timestamp_format(mut self, format: Option<&str>) -> Self {
+		self.timestamp_format = format.map(str::to_string);
+		self
+	}
+}
+
+impl Chat {
+	/**
+	 * A human readable transcript of the chat, oldest message first
+	 */
+	pub fn export(&self, format: ExportFormat, options: &ExportOptions) -> String {
+		let character = self.character_ref();
+		let bot_name = character
+			.chat_name_ref()
+			.as_deref()
+			.filter(|n: &&str| !n.trim().is_empty())
+			.unwrap_or(character.name_ref());
+		let mut messages: Vec<&Message> = self
+			.chat_messages_ref()
+			.iter()
+			.filter(|m: &&Message| !options.main_only || *m.is_main_ref())
+			.collect();
+		messages.sort_by_key(|m: &&Message| (*m.created_at_ref(), *m.id_ref()));
+
+		let title = format!("Chat with {}", character.name_ref());
+		let mut out = String::new();
+		match format {
+			ExportFormat::Markdown => {
+				let _ = writeln!(out, "# {}\n", title);
+			}
+			ExportFormat::Html => {
+				let _ = write!(
+					out,
+					"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+					<style>body{{font-family:sans-serif;max-width:48em;margin:auto}}\
+					.message{{margin:1em 0}}.bot .speaker{{color:#7a4fd6}}.user .speaker{{color:#2a7ad6}}\
+					time{{color:#888;font-size:.8em;margin-left:.5em}}</style>\n</head>\n<body>\n<h1>{title}</h1>\n",
+					title = escape_html(&title)
+				);
+			}
+			ExportFormat::PlainText => {
+				let _ = writeln!(out, "{}\n", title);
+			}
+		}
+
+		for message in messages {
+			let speaker = if *message.is_bot_ref() {
+				bot_name
+			} else {
+				options.user_name.as_str()
+			};
+			let timestamp = options
+				.timestamp_format
+				.as_ref()
+				.map(|f: &String| message.created_at_ref().format(f).to_string());
+			let text = message.message_ref().trim();
+			match format {
+				ExportFormat::Markdown => {
+					let _ = match &timestamp {
+						Some(t) => writeln!(out, "**{}** _{}_\n\n{}\n", speaker, t, text),
+						None => writeln!(out, "**{}**\n\n{}\n", speaker, text),
+					};
+				}
+				ExportFormat::Html => {
+					let _ = write!(
+						out,
+						"<div class=\"message {}\">\n<div><span class=\"speaker\">{}</span>",
+						if *message.is_bot_ref() { "bot" } else { "user" },
+						escape_html(speaker)
+					);
+					if let Some(t) = &timestamp {
+						let _ = write!(
+							out,
+							"<time datetime=\"{}\">{}</time>",
+							message.created_at_ref().to_rfc3339(),
+							escape_html(t)
+						);
+					}
+					let _ = writeln!(
+						out,
+						"</div>\n<p>{}</p>\n</div>",
+						escape_html(text).replace('\n', "<br>\n")
+					);
+				}
+				ExportFormat::PlainText => {
+					let _ = match &timestamp {
+						Some(t) => writeln!(out, "[{}] {}:\n{}\n", t, speaker, text),
+						None => writeln!(out, "{}:\n{}\n", speaker, text),
+					};
+				}
+			}
+		}
+
+		if format == ExportFormat::Html {
+			out.push_str("</body>\n</html>\n");
+		}
+		out
+	}
+}
+
+fn escape_html(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
@@ -0,0 +1,82 @@
+use super::{character::Character, error::HampterError, misc, schema};
+use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
+use futures::Stream;
+use getters2::Getters;
+use serde;
+
+/**
+ * A page of the characters the current user marked as favourite
+ */
+#[derive(serde::Deserialize, serde::Serialize, Getters)]
+pub struct Favourites {
+	data: Vec<Character>,
+	total: u64,
+	size: u8,
+	page: u64,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Favourites, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+schema::modeled!(Favourites, ["data", "total", "size", "page"]);
+
+impl Favourites {
+	/**
+	 * Request a page of the favourites of the current user, starting at 1
+	 */
+	pub async fn list<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+		page: u32,
+	) -> Result<Favourites, HampterError> {
+		client
+			.send(client.get(format!(
+				"https://janitorai.com/hampter/favorites?page={}",
+				page
+			))?)
+			.await?
+			.json::<Favourites>()
+			.await
+	}
+
+	/**
+	 * Every favourite of the current user
+	 */
+	pub fn stream<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> impl Stream<Item = Result<Character, HampterError>> + '_ {
+		misc::paged(move |page: u32| async move {
+			let res = Self::list(client, page).await?;
+			let total = res.total;
+			Ok((res.into_data(), total))
+		})
+	}
+
+	pub fn into_data(self) -> Vec<Character> {
+		self.data
+	}
+}
+
+impl Character {
+	/**
+	 * Add a character to the favourites of the current user
+	 */
+	pub async fn favourite<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client
+			.send(client.post(format!("https://janitorai.com/hampter/favorites/{}", id))?)
+			.await?;
+		Ok(())
+	}
+
+	pub async fn unfavourite<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client
+			.send(client.delete(format!("https://janitorai.com/hampter/favorites/{}", id))?)
+			.await?;
+		Ok(())
+	}
+}
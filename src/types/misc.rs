@@ -1,9 +1,55 @@
+use super::error::HampterError;
+use futures::Stream;
 use serde::Deserialize;
+use std::future::Future;
 
+/**
+ * Accepts numbers sent as strings, and plain numbers so our own serialized models read back
+ */
 pub fn u64_from_string<'de, D>(deserializer: D) -> Result<u64, D::Error>
 where
 	D: serde::Deserializer<'de>,
 {
-	let s: &str = Deserialize::deserialize(deserializer)?;
-	s.parse().map_err(serde::de::Error::custom)
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum StringOrNumber {
+		String(String),
+		Number(u64),
+	}
+
+	match StringOrNumber::deserialize(deserializer)? {
+		StringOrNumber::String(s) => s.parse().map_err(serde::de::Error::custom),
+		StringOrNumber::Number(n) => Ok(n),
+	}
+}
+
+/**
+ * The items of consecutive pages starting at 1, `fetch` returns a page and the reported total
+ * The following page is only requested once the current one is used up. Stops at an empty page
+ * or once `total` items were yielded.
+ */
+pub(crate) fn paged<'a, I, F, Fut>(mut fetch: F) -> impl Stream<Item = Result<I, HampterError>> + 'a
+where
+	I: 'a,
+	F: FnMut(u32) -> Fut + 'a,
+	Fut: Future<Output = Result<(Vec<I>, u64), HampterError>> + 'a,
+{
+	async_stream::try_stream! {
+		let mut page: u32 = 1;
+		let mut seen: u64 = 0;
+		loop {
+			let (items, total) = fetch(page).await?;
+			if items.is_empty() {
+				break;
+			}
+			seen += items.len() as u64;
+			for item in items {
+				yield item;
+			}
+			if seen >= total {
+				break;
+			}
+			page += 1;
+		}
+	}
 }
@@ -4,6 +4,11 @@ pub mod persona;
 pub mod tag;
 pub mod character;
 pub mod review;
+pub mod favourite;
+pub mod export;
+pub mod sillytavern;
 pub mod error;
+pub mod schema;
+pub mod secret;
 
 pub(crate) mod misc;
\ No newline at end of file
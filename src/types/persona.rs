@@ -1,8 +1,10 @@
-use super::error::HampterError;
+use super::{error::HampterError, schema};
 use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
 use chrono;
 use getters2::Getters;
 use serde;
+use serde_json::json;
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Persona {
@@ -12,37 +14,61 @@ pub struct Persona {
 	appearance: String,
 	created_at: chrono::DateTime<chrono::Utc>,
 	updated_at: chrono::DateTime<chrono::Utc>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Persona, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Persona, ["id", "name", "avatar", "appearance", "created_at", "updated_at"]);
 
 impl Persona {
 	/**
 	 * Get a list of the clients personas
 	 */
-	pub async fn list(client: &AuthorizedClient) -> Result<Vec<Persona>, HampterError> {
-		Ok(client
-			.client()
-			.get("https://janitorai.com/hampter/personas/mine")
-			.send()
+	pub async fn list<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<Persona>, HampterError> {
+		client
+			.send(client.get("https://janitorai.com/hampter/personas/mine")?)
 			.await?
-			.error_for_status()?
 			.json::<Vec<Persona>>()
-			.await?)
+			.await
 	}
 
 	/**
 	 * Fetch a persona by its ID
 	 */
-	pub async fn get(
+	pub async fn get<T: HttpTransport>(
 		id: &str,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<Persona, HampterError> {
-		Ok(client
-			.client()
-			.get(format!("https://janitorai.com/hampter/personas/{}", id))
-			.send()
+		client
+			.send(client.get(format!("https://janitorai.com/hampter/personas/{}", id))?)
 			.await?
-			.error_for_status()?
 			.json::<Persona>()
-			.await?)
+			.await
+	}
+
+	/**
+	 * Create a persona, `avatar` is the URL or file name of an already uploaded image
+	 */
+	pub async fn create<T: HttpTransport>(
+		name: &str,
+		appearance: &str,
+		avatar: Option<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<Persona, HampterError> {
+		client
+			.send(
+				client
+					.post("https://janitorai.com/hampter/personas")?
+					.json(&json!({
+						"name": name,
+						"appearance": appearance,
+						"avatar": avatar,
+					}))?,
+			)
+			.await?
+			.json::<Persona>()
+			.await
 	}
 }
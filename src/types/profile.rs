@@ -1,26 +1,55 @@
-use super::{error::HampterError, misc};
+use super::{error::HampterError, misc, schema, secret::{self, Secret}};
+use super::character::Character;
 use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
 use getters2::Getters;
 use serde;
-use std::collections::HashMap;
+// values of `Config::api` and `Config::open_ai_mode`
+// Only "api_key" was seen in a response, the `api` values follow the names of the key fields.
+const API_JANITOR: &str = ""; // what `Config::default` has always sent
+const API_OPENAI: &str = "openai";
+const API_CLAUDE: &str = "claude";
+const OPENAI_MODE_KEY: &str = "api_key";
 
-#[derive(serde::Deserialize, serde::Serialize, Getters)]
-struct GenerationSettings {
-	temperature: f32,    // default to 1.1
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct GenerationSettings {
+	temperature: f64,    // default to 1.1
 	max_new_token: i16,  // default to 500
 	context_length: i32, // default to 16384 probably fix
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<GenerationSettings, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(GenerationSettings, ["temperature", "max_new_token", "context_length"]);
 impl Default for GenerationSettings {
 	fn default() -> Self {
 		Self {
 			temperature: 1.1,
 			max_new_token: 500,
 			context_length: 16384,
+			extra: serde_json::Map::new(),
 		}
 	}
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Getters)]
+impl GenerationSettings {
+	pub fn temperature(mut self, temperature: f64) -> Self {
+		self.temperature = temperature;
+		self
+	}
+
+	pub fn max_new_token(mut self, max_new_token: i16) -> Self {
+		self.max_new_token = max_new_token;
+		self
+	}
+
+	pub fn context_length(mut self, context_length: i32) -> Self {
+		self.context_length = context_length;
+		self
+	}
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
 pub struct Config {
 	chat_custom_background_image: String, // default ""
 	chat_custom_background_opacity: u8,   // default 10
@@ -33,20 +62,64 @@ pub struct Config {
 	api: String,          // unsure what the default is prob janitor though
 	llm_prompt: String,   // ""
 	open_ai_mode: String, // also no clue about the default
-	#[serde(skip)]
-	text_streaming: bool,
-	#[serde(skip)]
-	immersive_mode: bool,
-	#[serde(skip)]
-	debug_mode: bool,
-	#[serde(skip)]
-	use_pygmalion_format: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	text_streaming: Option<bool>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	immersive_mode: Option<bool>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	debug_mode: Option<bool>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	use_pygmalion_format: Option<bool>,
 	#[serde(rename = "openAIKey")]
-	open_aikey: Option<String>,
+	open_aikey: Option<Secret>,
 	#[serde(rename = "claudeApiKey")]
-	claude_api_key: Option<String>,
+	claude_api_key: Option<Secret>,
 	#[serde(rename = "reverseProxyKey")]
-	reverse_proxy_key: Option<String>,
+	reverse_proxy_key: Option<Secret>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Config, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+schema::modeled!(Config, [
+	"chat_custom_background_image", "chat_custom_background_opacity",
+	"chat_custom_background_blur", "chat_custom_foreground_color", "chat_custom_font_size",
+	"show_clouds", "show_swords", "generation_settings", "api", "llm_prompt", "open_ai_mode",
+	"text_streaming", "immersive_mode", "debug_mode", "use_pygmalion_format", "openAIKey",
+	"claudeApiKey", "reverseProxyKey",
+]);
+
+/**
+ * The LLM that answers in chats
+ * There is no reverse proxy variant: the fields holding the proxy URL and model aren't known, so
+ * selecting a proxy can't be written yet. Its key is set with `Config::reverse_proxy_key`.
+ * A config selecting a proxy, or any other `api` value the crate doesn't know, reads as `Other`.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum ApiBackend {
+	JanitorLlm,
+	OpenAi { key: Secret },
+	Claude { key: Secret },
+	Other { api: String }, // the raw `api` value
+}
+
+impl ApiBackend {
+	/**
+	 * Make sure every key and URL the backend needs is present
+	 */
+	pub fn validate(&self) -> Result<(), HampterError> {
+		fn required(value: &str, what: &str) -> Result<(), HampterError> {
+			if value.trim().is_empty() {
+				return Err(HampterError::InvalidInput(format!("{} is required", what)));
+			}
+			Ok(())
+		}
+		match self {
+			Self::JanitorLlm => Ok(()),
+			Self::OpenAi { key } => required(key.expose(), "OpenAI API key"),
+			Self::Claude { key } => required(key.expose(), "Claude API key"),
+			Self::Other { api } => required(api, "api"),
+		}
+	}
 }
 
 impl Default for Config {
@@ -63,17 +136,148 @@ impl Default for Config {
 			api: "".to_string(),
 			llm_prompt: "".to_string(),
 			open_ai_mode: "api_key".to_string(), // ? was like that in a response *shrug*
-			text_streaming: true,
-			immersive_mode: false,
-			debug_mode: false,
-			use_pygmalion_format: true,
+			text_streaming: Some(true),
+			immersive_mode: Some(false),
+			debug_mode: Some(false),
+			use_pygmalion_format: Some(true),
 			open_aikey: None,
 			claude_api_key: None,
 			reverse_proxy_key: None,
+			extra: serde_json::Map::new(),
 		}
 	}
 }
 
+/**
+ * Setters to derive a changed config, e.g. from `profile.config_ref().clone()`
+ */
+impl Config {
+	pub fn chat_custom_background_image(mut self, image: &str) -> Self {
+		self.chat_custom_background_image = image.to_string();
+		self
+	}
+
+	pub fn chat_custom_background_opacity(mut self, opacity: u8) -> Self {
+		self.chat_custom_background_opacity = opacity;
+		self
+	}
+
+	pub fn chat_custom_background_blur(mut self, blur: u8) -> Self {
+		self.chat_custom_background_blur = blur;
+		self
+	}
+
+	pub fn chat_custom_foreground_color(mut self, color: &str) -> Self {
+		self.chat_custom_foreground_color = color.to_string();
+		self
+	}
+
+	pub fn chat_custom_font_size(mut self, font_size: u8) -> Self {
+		self.chat_custom_font_size = font_size;
+		self
+	}
+
+	pub fn show_clouds(mut self, show_clouds: bool) -> Self {
+		self.show_clouds = show_clouds;
+		self
+	}
+
+	pub fn show_swords(mut self, show_swords: bool) -> Self {
+		self.show_swords = show_swords;
+		self
+	}
+
+	pub fn generation_settings(mut self, generation_settings: GenerationSettings) -> Self {
+		self.generation_settings = generation_settings;
+		self
+	}
+
+	/**
+	 * Shorthand to change the generation settings in place
+	 */
+	pub fn with_generation_settings(
+		mut self,
+		f: impl FnOnce(GenerationSettings) -> GenerationSettings,
+	) -> Self {
+		self.generation_settings = f(self.generation_settings);
+		self
+	}
+
+	pub fn llm_prompt(mut self, llm_prompt: &str) -> Self {
+		self.llm_prompt = llm_prompt.to_string();
+		self
+	}
+
+	pub fn text_streaming(mut self, text_streaming: bool) -> Self {
+		self.text_streaming = Some(text_streaming);
+		self
+	}
+
+	pub fn immersive_mode(mut self, immersive_mode: bool) -> Self {
+		self.immersive_mode = Some(immersive_mode);
+		self
+	}
+
+	pub fn debug_mode(mut self, debug_mode: bool) -> Self {
+		self.debug_mode = Some(debug_mode);
+		self
+	}
+
+	pub fn use_pygmalion_format(mut self, use_pygmalion_format: bool) -> Self {
+		self.use_pygmalion_format = Some(use_pygmalion_format);
+		self
+	}
+
+	pub fn reverse_proxy_key(mut self, key: Option<Secret>) -> Self {
+		self.reverse_proxy_key = key;
+		self
+	}
+
+	/**
+	 * The backend selected by `api` as stored, a missing key is an empty `Secret`
+	 */
+	pub fn backend(&self) -> ApiBackend {
+		match self.api.as_str() {
+			API_JANITOR => ApiBackend::JanitorLlm,
+			API_OPENAI => ApiBackend::OpenAi {
+				key: self.open_aikey.clone().unwrap_or_default(),
+			},
+			API_CLAUDE => ApiBackend::Claude {
+				key: self.claude_api_key.clone().unwrap_or_default(),
+			},
+			api => ApiBackend::Other {
+				api: api.to_string(),
+			},
+		}
+	}
+
+	/**
+	 * Switch the backend, keys of the other backends are kept
+	 * Persist it with `Profile::update_config`
+	 */
+	pub fn with_backend(mut self, backend: ApiBackend) -> Result<Self, HampterError> {
+		backend.validate()?;
+		match backend {
+			ApiBackend::JanitorLlm => {
+				self.api = API_JANITOR.to_string();
+			}
+			ApiBackend::OpenAi { key } => {
+				self.api = API_OPENAI.to_string();
+				self.open_ai_mode = OPENAI_MODE_KEY.to_string();
+				self.open_aikey = Some(key);
+			}
+			ApiBackend::Claude { key } => {
+				self.api = API_CLAUDE.to_string();
+				self.claude_api_key = Some(key);
+			}
+			ApiBackend::Other { api } => {
+				self.api = api;
+			}
+		}
+		Ok(self)
+	}
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Persona {
 	id: String,
@@ -82,7 +286,11 @@ pub struct Persona {
 	appearance: String,
 	created_at: chrono::DateTime<chrono::Utc>,
 	updated_at: chrono::DateTime<chrono::Utc>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Persona, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Persona, ["id", "name", "avatar", "appearance", "created_at", "updated_at"]);
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Profile {
@@ -96,36 +304,293 @@ pub struct Profile {
 	followers_count: u64, // response contains a String
 	config: Config,
 	profile: String,
-	block_list: HashMap<String, Vec<String>>,
-	// 	"bots": [],
-	// 	"creators": [],
-	// 	"tags": [],
-	// 	"keywords": []
-	// },
-	// style: {},
+	block_list: BlockList,
 	created_at: chrono::DateTime<chrono::Utc>,
-	// user_roles: [],
 	personas: Option<Vec<Persona>>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Profile, _>")]
+	extra: serde_json::Map<String, serde_json::Value>, // style, user_roles, ...
+}
+schema::modeled!(Profile, [
+	"id", "avatar", "name", "user_name", "about_me", "is_verified", "followers_count", "config",
+	"profile", "block_list", "created_at", "personas",
+]);
+
+/**
+ * Content the user doesn't want to see
+ */
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize, Getters)]
+pub struct BlockList {
+	#[serde(default)]
+	bots: Vec<String>, // character IDs
+	#[serde(default)]
+	creators: Vec<String>, // creator IDs
+	#[serde(default)]
+	tags: Vec<String>,
+	#[serde(default)]
+	keywords: Vec<String>,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<BlockList, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+schema::modeled!(BlockList, ["bots", "creators", "tags", "keywords"]);
+
+impl BlockList {
+	/**
+	 * Returns false if the bot was already blocked
+	 */
+	pub fn block_bot(&mut self, character_id: &str) -> bool {
+		Self::insert(&mut self.bots, character_id)
+	}
+
+	/**
+	 * Returns false if the bot wasn't blocked
+	 */
+	pub fn unblock_bot(&mut self, character_id: &str) -> bool {
+		Self::remove(&mut self.bots, character_id)
+	}
+
+	pub fn block_creator(&mut self, creator_id: &str) -> bool {
+		Self::insert(&mut self.creators, creator_id)
+	}
+
+	pub fn unblock_creator(&mut self, creator_id: &str) -> bool {
+		Self::remove(&mut self.creators, creator_id)
+	}
+
+	pub fn block_tag(&mut self, tag: &str) -> bool {
+		Self::insert(&mut self.tags, tag)
+	}
+
+	pub fn unblock_tag(&mut self, tag: &str) -> bool {
+		Self::remove(&mut self.tags, tag)
+	}
+
+	pub fn block_keyword(&mut self, keyword: &str) -> bool {
+		Self::insert(&mut self.keywords, keyword)
+	}
+
+	pub fn unblock_keyword(&mut self, keyword: &str) -> bool {
+		Self::remove(&mut self.keywords, keyword)
+	}
+
+	/**
+	 * Whether the block list hides a character
+	 * Tags match tag IDs, names, slugs and custom tags, keywords match name and description.
+	 * Everything but IDs is compared case-insensitively.
+	 */
+	pub fn is_blocked(&self, character: &Character) -> bool {
+		if self.bots.contains(character.id_ref())
+			|| self.creators.contains(character.creator_id_ref())
+		{
+			return true;
+		}
+		let tags: Vec<String> = self.tags.iter().map(|t: &String| t.to_lowercase()).collect();
+		if character.tags_matching(&tags).next().is_some()
+			|| character.custom_tags_matching(&tags).next().is_some()
+		{
+			return true;
+		}
+		let text = format!("{} {}", character.name_ref(), character.description_ref()).to_lowercase();
+		self.keywords
+			.iter()
+			.any(|k: &String| !k.is_empty() && text.contains(&k.to_lowercase()))
+	}
+
+	/**
+	 * Drop every blocked character, e.g. from `QueryResponse::data`
+	 */
+	pub fn filter(&self, characters: Vec<Character>) -> Vec<Character> {
+		characters
+			.into_iter()
+			.filter(|c: &Character| !self.is_blocked(c))
+			.collect()
+	}
+
+	fn insert(list: &mut Vec<String>, value: &str) -> bool {
+		if list.iter().any(|v: &String| v == value) {
+			return false;
+		}
+		list.push(value.to_string());
+		true
+	}
+
+	fn remove(list: &mut Vec<String>, value: &str) -> bool {
+		let len = list.len();
+		list.retain(|v: &String| v != value);
+		list.len() != len
+	}
+}
+
+/**
+ * The editable parts of the client profile, fields left at `None` aren't touched
+ */
+#[derive(Default, serde::Serialize, Getters)]
+pub struct ProfileUpdate {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	user_name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	about_me: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	avatar: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	profile: Option<String>, // HTML/markdown shown on the profile page
+}
+
+impl ProfileUpdate {
+	pub fn name(mut self, name: &str) -> Self {
+		self.name = Some(name.to_string());
+		self
+	}
+
+	pub fn user_name(mut self, user_name: &str) -> Self {
+		self.user_name = Some(user_name.to_string());
+		self
+	}
+
+	pub fn about_me(mut self, about_me: &str) -> Self {
+		self.about_me = Some(about_me.to_string());
+		self
+	}
+
+	/**
+	 * URL or file name of an already uploaded avatar
+	 */
+	pub fn avatar(mut self, avatar: &str) -> Self {
+		self.avatar = Some(avatar.to_string());
+		self
+	}
+
+	pub fn profile(mut self, profile: &str) -> Self {
+		self.profile = Some(profile.to_string());
+		self
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.name.is_none()
+			&& self.user_name.is_none()
+			&& self.about_me.is_none()
+			&& self.avatar.is_none()
+			&& self.profile.is_none()
+	}
+
+	/**
+	 * Reject blank names before a request is sent
+	 * The rules for user names aren't documented, the server's answer is the authority on them.
+	 */
+	pub fn validate(&self) -> Result<(), HampterError> {
+		if self.name.as_ref().is_some_and(|n: &String| n.trim().is_empty()) {
+			return Err(HampterError::InvalidInput("name must not be empty".to_string()));
+		}
+		if self
+			.user_name
+			.as_ref()
+			.is_some_and(|n: &String| n.trim().is_empty())
+		{
+			return Err(HampterError::InvalidInput(
+				"user name must not be empty".to_string(),
+			));
+		}
+		Ok(())
+	}
+
+	/**
+	 * Drop fields that already hold the requested value
+	 */
+	fn without_unchanged(mut self, current: &Profile) -> Self {
+		fn changed(value: Option<String>, current: &str) -> Option<String> {
+			value.filter(|v: &String| v != current)
+		}
+		self.name = changed(self.name, &current.name);
+		self.user_name = changed(self.user_name, &current.user_name);
+		self.about_me = changed(self.about_me, &current.about_me);
+		self.avatar = changed(self.avatar, &current.avatar);
+		self.profile = changed(self.profile, &current.profile);
+		self
+	}
 }
 impl Profile {
 	/**
 	 * Fetch a user profile
 	 * If no ID is provided the client profile will be used
 	 */
-	pub async fn get(
-		client: &AuthorizedClient,
+	pub async fn get<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
 		mut id: Option<&str>,
 	) -> Result<Profile, HampterError> {
-		Ok(client
-			.client()
-			.get(format!(
+		client
+			.send(client.get(format!(
 				"https://janitorai.com/hampter/profiles/{i}",
 				i = id.get_or_insert("mine")
-			))
-			.send()
+			))?)
 			.await?
-			.error_for_status()?
 			.json::<Profile>()
-			.await?)
+			.await
+	}
+
+	/**
+	 * Update the client profile, `self` has to be the client's own profile
+	 * Only fields that differ from `self` are sent. Returns the refreshed profile.
+	 */
+	pub async fn update<T: HttpTransport>(
+		&self,
+		update: ProfileUpdate,
+		client: &AuthorizedClient<T>,
+	) -> Result<Profile, HampterError> {
+		update.validate()?;
+		let update = update.without_unchanged(self);
+		if !update.is_empty() {
+			client
+				.send(
+					client
+						.patch("https://janitorai.com/hampter/profiles/mine")?
+						.json(&update)?,
+				)
+				.await?;
+		}
+		Self::get(client, None).await
+	}
+
+	/**
+	 * Persist a new config for the client profile, `self` has to be the client's own profile
+	 */
+	pub async fn update_config<T: HttpTransport>(
+		&mut self,
+		config: Config,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		let request = secret::exposed(|| {
+			client
+				.patch("https://janitorai.com/hampter/profiles/mine")?
+				.json(&serde_json::json!({
+					"config": config
+				}))
+		})?;
+		client.send(request).await?;
+		self.config = config;
+		Ok(())
+	}
+
+	/**
+	 * Persist a changed block list, `self` has to be the client's own profile
+	 */
+	pub async fn update_block_list<T: HttpTransport>(
+		&mut self,
+		block_list: BlockList,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client
+			.send(
+				client
+					.patch("https://janitorai.com/hampter/profiles/mine")?
+					.json(&serde_json::json!({
+						"block_list": block_list
+					}))?,
+			)
+			.await?;
+		self.block_list = block_list;
+		Ok(())
 	}
 }
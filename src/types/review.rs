@@ -1,8 +1,12 @@
-use super::error::HampterError;
+use super::{error::HampterError, schema};
 use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
 use chrono;
+use futures::Stream;
 use getters2::Getters;
 use serde;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct UserProfile {
@@ -10,7 +14,11 @@ pub struct UserProfile {
 	avatar: String,
 	user_name: Option<String>,
 	is_verified: bool,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<UserProfile, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(UserProfile, ["name", "avatar", "user_name", "is_verified"]);
 
 #[derive(serde::Deserialize, serde::Serialize, Getters)]
 pub struct Review {
@@ -24,26 +32,332 @@ pub struct Review {
 	comment_count: u32,
 	user_profiles: UserProfile,
 	moderator: bool,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Review, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+schema::modeled!(Review, [
+	"id", "user_id", "character_id", "created_at", "content", "is_like", "like_count",
+	"comment_count", "user_profiles", "moderator",
+]);
+
+#[derive(serde::Deserialize, serde::Serialize, Getters)]
+pub struct ReviewComment {
+	id: String,
+	review_id: String,
+	user_id: String,
+	created_at: chrono::DateTime<chrono::Utc>,
+	content: String,
+	user_profiles: UserProfile,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<ReviewComment, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+schema::modeled!(ReviewComment, [
+	"id", "review_id", "user_id", "created_at", "content", "user_profiles",
+]);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReviewSort {
+	Newest,
+	MostLiked,
+}
+impl std::fmt::Display for ReviewSort {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Newest => "newest",
+			Self::MostLiked => "most_liked",
+		})
+	}
 }
 
 impl Review {
 	/**
 	 * Request the reviews of a bot
 	 */
-	pub async fn get(
+	pub async fn get<T: HttpTransport>(
 		character_id: &str,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<Vec<Review>, HampterError> {
-		Ok(client
-			.client()
-			.get(format!(
+		client
+			.send(client.get(format!(
 				"https://janitorai.com/hampter/reviews/{}",
 				character_id
-			))
-			.send()
+			))?)
+			.await?
+			.json::<Vec<Review>>()
+			.await
+	}
+
+	/**
+	 * Request a single page of the reviews of a bot, starting at 1
+	 */
+	pub async fn get_page<T: HttpTransport>(
+		character_id: &str,
+		page: u32,
+		sort: Option<ReviewSort>,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<Review>, HampterError> {
+		client
+			.send(client.get(format!(
+				"https://janitorai.com/hampter/reviews/{}?page={}&sort={}",
+				character_id,
+				page,
+				sort.unwrap_or(ReviewSort::Newest)
+			))?)
 			.await?
-			.error_for_status()?
 			.json::<Vec<Review>>()
-			.await?)
+			.await
+	}
+
+	/**
+	 * Every review of a bot, fetched page by page as the stream is read
+	 * Only reviews matching `is_like` and `moderator` are yielded, `None` accepts both.
+	 * Stops at a page shorter than the first or one holding only reviews already seen, which is
+	 * what a server ignoring `page` sends.
+	 */
+	pub fn stream<'a, T: HttpTransport>(
+		character_id: &'a str,
+		sort: Option<ReviewSort>,
+		is_like: Option<bool>,
+		moderator: Option<bool>,
+		client: &'a AuthorizedClient<T>,
+	) -> impl Stream<Item = Result<Review, HampterError>> + 'a {
+		async_stream::try_stream! {
+			let mut page: u32 = 1;
+			let mut page_size: Option<usize> = None;
+			let mut seen: HashSet<String> = HashSet::new();
+			loop {
+				let reviews = Self::get_page(character_id, page, sort, client).await?;
+				let len = reviews.len();
+				let new: Vec<Review> = reviews
+					.into_iter()
+					.filter(|r: &Review| seen.insert(r.id.clone()))
+					.collect();
+				if new.is_empty() {
+					break;
+				}
+				for review in new {
+					if is_like.is_some_and(|l: bool| l != review.is_like)
+						|| moderator.is_some_and(|m: bool| m != review.moderator)
+					{
+						continue;
+					}
+					yield review;
+				}
+				if len < *page_size.get_or_insert(len) {
+					break;
+				}
+				page += 1;
+			}
+		}
+	}
+
+	/**
+	 * Review a bot, `is_like` is the thumbs up or down next to the text
+	 */
+	pub async fn create<T: HttpTransport>(
+		character_id: &str,
+		is_like: bool,
+		content: Option<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<Review, HampterError> {
+		client
+			.send(
+				client
+					.post("https://janitorai.com/hampter/reviews")?
+					.json(&json!({
+						"character_id": character_id,
+						"is_like": is_like,
+						"content": content,
+					}))?,
+			)
+			.await?
+			.json::<Review>()
+			.await
+	}
+
+	/**
+	 * Change the rating and text of a review, returns the updated review
+	 */
+	pub async fn update<T: HttpTransport>(
+		&self,
+		is_like: bool,
+		content: Option<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<Review, HampterError> {
+		client
+			.send(
+				client
+					.patch(format!("https://janitorai.com/hampter/reviews/{}", self.id))?
+					.json(&json!({
+						"is_like": is_like,
+						"content": content,
+					}))?,
+			)
+			.await?
+			.json::<Review>()
+			.await
+	}
+
+	pub async fn delete<T: HttpTransport>(
+		id: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client
+			.send(client.delete(format!("https://janitorai.com/hampter/reviews/{}", id))?)
+			.await?;
+		Ok(())
+	}
+
+	/**
+	 * Like the review, `like_count` is adjusted locally
+	 */
+	pub async fn like<T: HttpTransport>(
+		&mut self,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client
+			.send(client.post(format!(
+				"https://janitorai.com/hampter/reviews/{}/likes",
+				self.id
+			))?)
+			.await?;
+		self.like_count += 1;
+		Ok(())
+	}
+
+	pub async fn unlike<T: HttpTransport>(
+		&mut self,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		client
+			.send(client.delete(format!(
+				"https://janitorai.com/hampter/reviews/{}/likes",
+				self.id
+			))?)
+			.await?;
+		self.like_count = self.like_count.saturating_sub(1);
+		Ok(())
+	}
+
+	pub async fn get_comments<T: HttpTransport>(
+		&self,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<ReviewComment>, HampterError> {
+		client
+			.send(client.get(format!(
+				"https://janitorai.com/hampter/reviews/{}/comments",
+				self.id
+			))?)
+			.await?
+			.json::<Vec<ReviewComment>>()
+			.await
+	}
+
+	/**
+	 * Reply to the review, `comment_count` is adjusted locally
+	 */
+	pub async fn comment<T: HttpTransport>(
+		&mut self,
+		content: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<ReviewComment, HampterError> {
+		if content.trim().is_empty() {
+			return Err(HampterError::InvalidInput("a comment can't be empty".to_string()));
+		}
+		let comment = client
+			.send(
+				client
+					.post(format!(
+						"https://janitorai.com/hampter/reviews/{}/comments",
+						self.id
+					))?
+					.json(&json!({
+						"content": content,
+					}))?,
+			)
+			.await?
+			.json::<ReviewComment>()
+			.await?;
+		self.comment_count += 1;
+		Ok(comment)
+	}
+}
+
+#[derive(Clone, Getters)]
+pub struct Reviewer {
+	user_id: String,
+	name: String,
+	reviews: u32,
+	likes: u32, // likes received on their reviews
+}
+
+/**
+ * Aggregates over the reviews of a bot, e.g. collected from `Review::stream`
+ */
+#[derive(Clone, Getters)]
+pub struct ReviewSummary {
+	total: u32,
+	likes: u32,
+	dislikes: u32,
+	per_day: BTreeMap<chrono::NaiveDate, u32>,
+	top_reviewers: Vec<Reviewer>, // most reviews first, ties broken by likes received
+}
+
+impl ReviewSummary {
+	pub fn new(reviews: &[Review]) -> Self {
+		let mut per_day = BTreeMap::new();
+		let mut reviewers: HashMap<&str, Reviewer> = HashMap::new();
+		for review in reviews {
+			*per_day.entry(review.created_at.date_naive()).or_insert(0) += 1;
+			let reviewer = reviewers
+				.entry(review.user_id.as_str())
+				.or_insert_with(|| Reviewer {
+					user_id: review.user_id.clone(),
+					name: review.user_profiles.name.clone(),
+					reviews: 0,
+					likes: 0,
+				});
+			reviewer.reviews += 1;
+			reviewer.likes += review.like_count;
+		}
+		let mut top_reviewers: Vec<Reviewer> = reviewers.into_values().collect();
+		top_reviewers.sort_by(|a: &Reviewer, b: &Reviewer| {
+			b.reviews
+				.cmp(&a.reviews)
+				.then(b.likes.cmp(&a.likes))
+				.then(a.user_id.cmp(&b.user_id))
+		});
+		let likes = reviews.iter().filter(|r: &&Review| r.is_like).count() as u32;
+		Self {
+			total: reviews.len() as u32,
+			likes,
+			dislikes: reviews.len() as u32 - likes,
+			per_day,
+			top_reviewers,
+		}
+	}
+
+	/**
+	 * Share of positive reviews between 0 and 1, `None` without reviews
+	 */
+	pub fn like_ratio(&self) -> Option<f64> {
+		if self.total == 0 {
+			return None;
+		}
+		Some(self.likes as f64 / self.total as f64)
+	}
+
+	/**
+	 * Average reviews per day over the last `days` days up to and including `until`
+	 */
+	pub fn velocity(&self, until: chrono::NaiveDate, days: u32) -> f64 {
+		if days == 0 {
+			return 0.0;
+		}
+		let from = until - chrono::Duration::days(days as i64 - 1);
+		let count: u32 = self.per_day.range(from..=until).map(|(_, c)| c).sum();
+		count as f64 / days as f64
 	}
 }
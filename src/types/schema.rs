@@ -0,0 +1,174 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+use getters2::Getters;
+use serde::Deserialize;
+
+/**
+ * How unknown fields in server responses are treated
+ * Only applies while a response is deserialized, files and rows written by the crate are read
+ * as they are.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SchemaMode {
+	/// Unknown fields are kept in the `extra` map of the model, nothing else happens (default)
+	Off,
+	/// Unknown fields are collected for `schema_report` and logged the first time they show up
+	Diagnostic,
+	/// Like `Diagnostic` but responses with unknown fields fail to deserialize
+	Strict,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(0);
+static OBSERVED: Mutex<BTreeMap<&'static str, Observed>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+	static IN_RESPONSE: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Observed {
+	fields: &'static [&'static str],
+	instances: u64,
+	unknown: BTreeMap<String, u64>,
+}
+
+pub fn set_mode(mode: SchemaMode) {
+	MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn mode() -> SchemaMode {
+	match MODE.load(Ordering::Relaxed) {
+		1 => SchemaMode::Diagnostic,
+		2 => SchemaMode::Strict,
+		_ => SchemaMode::Off,
+	}
+}
+
+/**
+ * Fields of a model compared to what the server sent
+ */
+#[derive(Getters)]
+pub struct TypeReport {
+	name: &'static str,
+	modeled: &'static [&'static str],
+	instances: u64,
+	unknown: BTreeMap<String, u64>,
+}
+
+impl fmt::Display for TypeReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"{} ({} seen, {} fields modeled)",
+			self.name,
+			self.instances,
+			self.modeled.len()
+		)?;
+		for (field, count) in &self.unknown {
+			writeln!(f, "\tunknown `{}` in {}/{}", field, count, self.instances)?;
+		}
+		Ok(())
+	}
+}
+
+/**
+ * Every model deserialized since diagnostics were enabled, with the unknown fields it carried
+ */
+pub fn schema_report() -> Vec<TypeReport> {
+	OBSERVED
+		.lock()
+		.unwrap()
+		.iter()
+		.map(|(name, observed)| TypeReport {
+			name,
+			modeled: observed.fields,
+			instances: observed.instances,
+			unknown: observed.unknown.clone(),
+		})
+		.collect()
+}
+
+/**
+ * Forget everything collected so far
+ */
+pub fn reset_report() {
+	OBSERVED.lock().unwrap().clear();
+}
+
+/**
+ * Run `f` with the schema mode applied, wraps the deserialization of server responses
+ */
+pub(crate) fn in_response<R>(f: impl FnOnce() -> R) -> R {
+	struct Reset(bool);
+	impl Drop for Reset {
+		fn drop(&mut self) {
+			IN_RESPONSE.set(self.0);
+		}
+	}
+	let _reset = Reset(IN_RESPONSE.replace(true));
+	f()
+}
+
+/**
+ * A model whose unknown fields are captured in a flattened `extra` map
+ */
+pub(crate) trait Modeled {
+	const NAME: &'static str;
+	const FIELDS: &'static [&'static str];
+}
+
+/**
+ * Implements `Modeled` listing the serialized names of the modeled fields
+ */
+macro_rules! modeled {
+	($ty:ident, [$($field:literal),* $(,)?]) => {
+		impl $crate::types::schema::Modeled for $ty {
+			const NAME: &'static str = concat!(module_path!(), "::", stringify!($ty));
+			const FIELDS: &'static [&'static str] = &[$($field),*];
+		}
+	};
+}
+pub(crate) use modeled;
+
+/**
+ * `deserialize_with` for the flattened `extra` map of a model
+ */
+pub(crate) fn unknown_fields<'de, M: Modeled, D>(
+	deserializer: D,
+) -> Result<serde_json::Map<String, serde_json::Value>, D::Error>
+where
+	D: serde::Deserializer<'de>,
+{
+	let extra: serde_json::Map<String, serde_json::Value> = Deserialize::deserialize(deserializer)?;
+	let mode = mode();
+	if mode == SchemaMode::Off || !IN_RESPONSE.get() {
+		return Ok(extra);
+	}
+
+	let mut observed = OBSERVED.lock().unwrap();
+	let observed = observed.entry(M::NAME).or_insert_with(|| Observed {
+		fields: M::FIELDS,
+		instances: 0,
+		unknown: BTreeMap::new(),
+	});
+	observed.instances += 1;
+	for key in extra.keys() {
+		let count = observed.unknown.entry(key.clone()).or_insert(0);
+		if *count == 0 {
+			log::warn!("{}: unknown field `{}`", M::NAME, key);
+		}
+		*count += 1;
+	}
+
+	if mode == SchemaMode::Strict && !extra.is_empty() {
+		return Err(serde::de::Error::custom(format!(
+			"unknown fields in {}: {}",
+			M::NAME,
+			extra.keys().cloned().collect::<Vec<String>>().join(", ")
+		)));
+	}
+	Ok(extra)
+}
@@ -0,0 +1,89 @@
+use std::cell::Cell;
+use std::fmt;
+use zeroize::Zeroize;
+
+const REDACTED: &str = "[REDACTED]";
+
+thread_local! {
+	static EXPOSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/**
+ * A key or token that never shows up in logs
+ * Debug and Display print a placeholder, serializing yields `null` unless done within `exposed`,
+ * so secrets don't end up in exports or fixtures by accident.
+ * The string held by a `Secret` is zeroed on drop. Clones, and the header values and request
+ * bodies built from `expose`, are separate copies that are freed without being zeroed, so this
+ * doesn't keep a secret out of memory.
+ */
+#[derive(Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+	pub fn new(secret: impl Into<String>) -> Self {
+		Self(secret.into())
+	}
+
+	pub fn expose(&self) -> &str {
+		&self.0
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+
+/**
+ * Serialize secrets with their actual value while `f` runs, e.g. to send a config to the server
+ */
+pub fn exposed<R>(f: impl FnOnce() -> R) -> R {
+	struct Reset(bool);
+	impl Drop for Reset {
+		fn drop(&mut self) {
+			EXPOSED.with(|e: &Cell<bool>| e.set(self.0));
+		}
+	}
+	let _reset = Reset(EXPOSED.with(|e: &Cell<bool>| e.replace(true)));
+	f()
+}
+
+impl serde::Serialize for Secret {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		if EXPOSED.with(Cell::get) {
+			serializer.serialize_str(&self.0)
+		} else {
+			serializer.serialize_none()
+		}
+	}
+}
+
+impl fmt::Debug for Secret {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(REDACTED)
+	}
+}
+
+impl fmt::Display for Secret {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(REDACTED)
+	}
+}
+
+impl From<String> for Secret {
+	fn from(secret: String) -> Self {
+		Self(secret)
+	}
+}
+
+impl From<&str> for Secret {
+	fn from(secret: &str) -> Self {
+		Self(secret.to_string())
+	}
+}
+
+impl Drop for Secret {
+	fn drop(&mut self) {
+		self.0.zeroize();
+	}
+}
@@ -0,0 +1,245 @@
+/*!
+ * Conversion between JanitorAI chats and SillyTavern's chat files
+ * A SillyTavern chat is JSONL: a header line naming the participants, then one line per message.
+ * Alternative generations (`is_main == false`) become swipes of the bot message they belong to.
+ */
+use super::chat::{Chat, Message};
+use super::error::HampterError;
+use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
+use getters2::Getters;
+use std::path::Path;
+
+/**
+ * Format of `SillyTavernHeader::create_date`
+ */
+const CREATE_DATE_FORMAT: &str = "%Y-%m-%d@%Hh%Mm%Ss";
+
+/**
+ * Format SillyTavern writes `SillyTavernMessage::send_date` in, e.g. "January 5, 2024 3:07pm"
+ */
+const SEND_DATE_FORMAT: &str = "%B %-d, %Y %-I:%M%P";
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct SillyTavernHeader {
+	user_name: String,
+	character_name: String,
+	create_date: String,
+	#[serde(default)]
+	chat_metadata: serde_json::Map<String, serde_json::Value>,
+	#[serde(flatten)]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
+pub struct SillyTavernMessage {
+	name: String,
+	is_user: bool,
+	#[serde(default)]
+	send_date: serde_json::Value, // a formatted date, older versions wrote unix millis
+	mes: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	swipes: Option<Vec<String>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	swipe_id: Option<usize>,
+	#[serde(flatten)]
+	extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SillyTavernMessage {
+	/**
+	 * When the message was sent, `None` if the date can't be read
+	 */
+	pub fn sent_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+		match &self.send_date {
+			serde_json::Value::Number(millis) => {
+				chrono::DateTime::from_timestamp_millis(millis.as_i64()?)
+			}
+			serde_json::Value::String(date) => parse_send_date(date),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Getters)]
+pub struct SillyTavernChat {
+	header: SillyTavernHeader,
+	messages: Vec<SillyTavernMessage>,
+}
+
+impl SillyTavernChat {
+	/**
+	 * Convert a chat, `user_name` is the name of the user's messages, e.g. `Profile::name`
+	 */
+	pub fn from_chat(chat: &Chat, user_name: &str) -> Self {
+		let character = chat.character_ref();
+		let character_name = character
+			.chat_name_ref()
+			.clone()
+			.filter(|n: &String| !n.trim().is_empty())
+			.unwrap_or(character.name_ref().clone());
+		let mut ordered: Vec<&Message> = chat.chat_messages_ref().iter().collect();
+		ordered.sort_by_key(|m: &&Message| (*m.created_at_ref(), *m.id_ref()));
+
+		// every main message starts an entry, alternatives become swipes of the bot message next to them
+		let mut groups: Vec<Vec<&Message>> = vec![];
+		for message in ordered {
+			match groups.last_mut() {
+				Some(group)
+					if *message.is_bot_ref()
+						&& *group[0].is_bot_ref()
+						&& !(*message.is_main_ref()
+							&& group.iter().any(|m: &&Message| *m.is_main_ref())) =>
+				{
+					group.push(message)
+				}
+				_ => groups.push(vec![message]),
+			}
+		}
+
+		let messages = groups
+			.into_iter()
+			.map(|group: Vec<&Message>| {
+				let main = group
+					.iter()
+					.position(|m: &&Message| *m.is_main_ref())
+					.unwrap_or(group.len() - 1);
+				let message = group[main];
+				let swipes: Vec<String> = group
+					.iter()
+					.map(|m: &&Message| m.message_ref().clone())
+					.collect();
+				let is_user = !*message.is_bot_ref();
+				SillyTavernMessage {
+					name: if is_user {
+						user_name.to_string()
+					} else {
+						character_name.clone()
+					},
+					is_user,
+					send_date: serde_json::Value::String(
+						message
+							.created_at_ref()
+							.format(SEND_DATE_FORMAT)
+							.to_string(),
+					),
+					mes: message.message_ref().clone(),
+					swipe_id: (swipes.len() > 1).then_some(main),
+					swipes: (swipes.len() > 1).then_some(swipes),
+					extra: serde_json::Map::new(),
+				}
+			})
+			.collect();
+
+		Self {
+			header: SillyTavernHeader {
+				user_name: user_name.to_string(),
+				character_name,
+				create_date: chat
+					.chat_messages_ref()
+					.iter()
+					.map(|m: &Message| *m.created_at_ref())
+					.min()
+					.unwrap_or(*chat.chat_ref().updated_at_ref())
+					.format(CREATE_DATE_FORMAT)
+					.to_string(),
+				chat_metadata: serde_json::Map::new(),
+				extra: serde_json::Map::new(),
+			},
+			messages,
+		}
+	}
+
+	pub fn from_jsonl(jsonl: &str) -> Result<Self, HampterError> {
+		let mut lines = jsonl.lines().filter(|l: &&str| !l.trim().is_empty());
+		let header =
+			serde_json::from_str(lines.next().ok_or_else(|| {
+				HampterError::InvalidInput("empty SillyTavern chat".to_string())
+			})?)?;
+		let messages = lines
+			.map(serde_json::from_str)
+			.collect::<Result<Vec<SillyTavernMessage>, serde_json::Error>>()?;
+		Ok(Self { header, messages })
+	}
+
+	pub fn to_jsonl(&self) -> Result<String, HampterError> {
+		let mut jsonl = serde_json::to_string(&self.header)?;
+		for message in &self.messages {
+			jsonl.push('\n');
+			jsonl.push_str(&serde_json::to_string(message)?);
+		}
+		jsonl.push('\n');
+		Ok(jsonl)
+	}
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, HampterError> {
+		Self::from_jsonl(&std::fs::read_to_string(path)?)
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HampterError> {
+		Ok(std::fs::write(path, self.to_jsonl()?)?)
+	}
+
+	/**
+	 * The messages as they'd be sent to the chat `chat_id`, unselected swipes become alternatives
+	 * A missing, unreadable or earlier `send_date` takes the previous message's time, so sorting by
+	 * `created_at` keeps the transcript order.
+	 */
+	pub fn to_messages(&self, chat_id: u64) -> Vec<Message> {
+		let mut messages = vec![];
+		let mut last = self
+			.messages
+			.iter()
+			.find_map(SillyTavernMessage::sent_at)
+			.unwrap_or_else(chrono::Utc::now);
+		for message in &self.messages {
+			let sent_at = message.sent_at().map_or(last, |date| date.max(last));
+			last = sent_at;
+			let main = message.swipe_id.unwrap_or(0);
+			let swipes = message.swipes.clone().unwrap_or_default();
+			messages.push(
+				Message::new(None, !message.is_user, true, chat_id, &message.mes, None)
+					.with_created_at(sent_at),
+			);
+			for (i, swipe) in swipes.iter().enumerate() {
+				if i == main || *swipe == message.mes {
+					continue;
+				}
+				messages.push(
+					Message::new(None, !message.is_user, false, chat_id, swipe, None)
+						.with_created_at(sent_at),
+				);
+			}
+		}
+		messages
+	}
+}
+
+impl Chat {
+	/**
+	 * Replay a SillyTavern chat into a new chat with `character_id`
+	 * If the new chat opens with the character's greeting, the transcript's first bot message
+	 * replaces its text instead of being sent a second time.
+	 */
+	pub async fn import_sillytavern<T: HttpTransport>(
+		character_id: &str,
+		transcript: &SillyTavernChat,
+		client: &AuthorizedClient<T>,
+	) -> Result<Chat, HampterError> {
+		Chat::replay(character_id, transcript.to_messages(0), client).await
+	}
+}
+
+fn parse_send_date(date: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+	if let Ok(date) = chrono::DateTime::parse_from_rfc3339(date) {
+		return Some(date.with_timezone(&chrono::Utc));
+	}
+	[
+		"%B %d, %Y %I:%M%P",
+		"%B %d, %Y %I:%M %P",
+		CREATE_DATE_FORMAT,
+	]
+	.iter()
+	.find_map(|format: &&str| chrono::NaiveDateTime::parse_from_str(date.trim(), format).ok())
+	.map(|date: chrono::NaiveDateTime| date.and_utc())
+}
@@ -1,13 +1,17 @@
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use crate::auth::AuthorizedClient;
+use crate::transport::HttpTransport;
 use chrono;
 use getters2::Getters;
 use serde_json::json;
 
-use super::error::HampterError;
+use super::{error::HampterError, schema};
 
-#[derive(serde::Deserialize, serde::Serialize, Getters)]
+#[derive(Clone, serde::Deserialize, serde::Serialize, Getters)]
 pub struct Tag {
 	id: u32,
 	#[serde(deserialize_with = "deserialize_datetime")]
@@ -15,7 +19,11 @@ pub struct Tag {
 	name: String,
 	slug: String,
 	description: String,
+	#[serde(flatten)]
+	#[serde(deserialize_with = "schema::unknown_fields::<Tag, _>")]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
+schema::modeled!(Tag, ["id", "created_at", "name", "slug", "description"]);
 fn deserialize_datetime<'de, D>(deserializer: D) -> Result<chrono::DateTime<chrono::Utc>, D::Error>
 where
 	D: serde::Deserializer<'de>,
@@ -35,22 +43,22 @@ impl Tag {
 	/**
 	 * Request the list of "official" tags introduced by JanitorAI
 	 */
-	pub async fn get_tag_list(client: &AuthorizedClient) -> Result<Vec<Tag>, HampterError> {
-		Ok(client
-			.client()
-			.get("https://janitorai.com/hampter/tags")
-			.send()
+	pub async fn get_tag_list<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<Tag>, HampterError> {
+		client
+			.send(client.get("https://janitorai.com/hampter/tags")?)
 			.await?
-			.error_for_status()?
 			.json::<Vec<Tag>>()
-			.await?
-		)
+			.await
 	}
 
 	/**
 	 * Returns the list of custom tags the client is following
 	 */
-	pub async fn get_following_tags(client: &AuthorizedClient) -> Result<Vec<String>, HampterError> {
+	pub async fn get_following_tags<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
 		/**
 		 * These are custom tags
 		 */
@@ -60,31 +68,30 @@ impl Tag {
 		}
 
 		Ok(client
-			.client()
-			.get("https://janitorai.com/hampter/following/tags")
-			.send()
+			.send(client.get("https://janitorai.com/hampter/following/tags")?)
 			.await?
-			.error_for_status()?
 			.json::<FollowTags>()
 			.await?
-			.following_tags
-		)
+			.following_tags)
 	}
 
 	/**
 	 * Update the list of the tags the client is following
 	 * This overwrites the previous list!
 	 */
-	pub async fn follow_tags(tags: Vec<&str>, client: &AuthorizedClient) -> Result<(), HampterError> {
+	pub async fn follow_tags<T: HttpTransport>(
+		tags: Vec<&str>,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
 		client
-			.client()
-			.post("https://janitorai.com/hampter/following/tags")
-			.json(&json!({
-				"custom_tags": tags
-			}))
-			.send()
-			.await?
-			.error_for_status()?;
+			.send(
+				client
+					.post("https://janitorai.com/hampter/following/tags")?
+					.json(&json!({
+						"custom_tags": tags
+					}))?,
+			)
+			.await?;
 		Ok(())
 	}
 
@@ -93,26 +100,451 @@ impl Tag {
 	 * Search query `prefix` has to be at least 3 letters long for a server response.
 	 * THe server response consists of an array with zero to five custom tags.
 	 */
-	pub async fn get_tag_suggestion(
+	pub async fn get_tag_suggestion<T: HttpTransport>(
 		prefix: &str,
-		client: &AuthorizedClient,
+		client: &AuthorizedClient<T>,
 	) -> Result<Vec<String>, HampterError> {
 		if prefix.len() < 3 {
 			return Ok(vec![]);
 		}
 		Ok(client
-			.client()
-			.get(format!(
+			.send(client.get(format!(
 				"https://janitorai.com/hampter/characters/tags/suggest?prefix={}",
 				prefix
-			))
-			.send()
+			))?)
 			.await?
-			.error_for_status()?
 			.json::<HashMap<String, Vec<String>>>()
 			.await?
 			.get("suggestions")
 			.get_or_insert(&vec![])
 			.to_owned())
 	}
+
+	/**
+	 * Follow a single custom tag, keeping the ones already followed
+	 * The tag is lowercased and has to show up in `get_tag_suggestion`, unless it's too short to get
+	 * suggestions or they're cut off at the limit, where an exact tag can be ranked below the others.
+	 * Returns the final list.
+	 */
+	pub async fn follow<T: HttpTransport>(
+		tag: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
+		let tag = normalize_custom_tag(tag);
+		if tag.is_empty() {
+			return Err(HampterError::InvalidInput(
+				"a tag can't be empty".to_string(),
+			));
+		}
+		if tag.chars().count() >= SUGGESTION_MIN_PREFIX {
+			let suggestions = normalize_custom_tags(Self::get_tag_suggestion(&tag, client).await?);
+			if suggestions.len() < SUGGESTION_LIMIT && !suggestions.contains(&tag) {
+				return Err(HampterError::InvalidInput(format!(
+					"`{}` isn't a known custom tag",
+					tag
+				)));
+			}
+		}
+		Self::update_following(
+			client,
+			|tags: &mut Vec<String>| {
+				if !tags.contains(&tag) {
+					tags.push(tag.clone());
+				}
+			},
+			|tags: &[String]| tags.contains(&tag),
+		)
+		.await
+	}
+
+	/**
+	 * Stop following a single custom tag, keeping the others. Returns the final list.
+	 */
+	pub async fn unfollow<T: HttpTransport>(
+		tag: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Vec<String>, HampterError> {
+		let tag = normalize_custom_tag(tag);
+		Self::update_following(
+			client,
+			|tags: &mut Vec<String>| tags.retain(|t: &String| *t != tag),
+			|tags: &[String]| !tags.contains(&tag),
+		)
+		.await
+	}
+
+	/**
+	 * Read, modify and write the followed tags, then read them back to check `done`
+	 * Another client overwriting the list in between only costs a retry, `HampterError::NotApplied`
+	 * is returned once `FOLLOW_ATTEMPTS` are used up.
+	 */
+	pub(crate) async fn update_following<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+		mut modify: impl FnMut(&mut Vec<String>),
+		done: impl Fn(&[String]) -> bool,
+	) -> Result<Vec<String>, HampterError> {
+		for _ in 0..FOLLOW_ATTEMPTS {
+			let mut tags = normalize_custom_tags(Self::get_following_tags(client).await?);
+			if done(&tags) {
+				return Ok(tags);
+			}
+			modify(&mut tags);
+			Self::follow_tags(tags.iter().map(String::as_str).collect(), client).await?;
+			let tags = normalize_custom_tags(Self::get_following_tags(client).await?);
+			if done(&tags) {
+				return Ok(tags);
+			}
+		}
+		Err(HampterError::NotApplied(format!(
+			"followed tags still not updated after {} attempts",
+			FOLLOW_ATTEMPTS
+		)))
+	}
+}
+
+/**
+ * How long the official tag list is trusted before `TagRegistry::refresh_if_stale` fetches it again
+ */
+const TAG_LIST_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/**
+ * How often `Tag::follow` and `Tag::unfollow` retry when another client changed the list meanwhile
+ */
+const FOLLOW_ATTEMPTS: u32 = 3;
+
+/**
+ * Shortest prefix `Tag::get_tag_suggestion` answers and the most suggestions it returns
+ */
+const SUGGESTION_MIN_PREFIX: usize = 3;
+const SUGGESTION_LIMIT: usize = 5;
+
+/**
+ * The official tag list indexed by id, slug and name
+ * Load it once with `TagRegistry::load` and keep it around, or persist it with `save` for offline use.
+ */
+pub struct TagRegistry {
+	tags: Vec<Tag>,
+	fetched_at: chrono::DateTime<chrono::Utc>,
+	ttl: Duration,
+	by_id: HashMap<u32, usize>,
+	by_slug: HashMap<String, usize>,
+	by_name: HashMap<String, usize>,
+}
+
+/**
+ * On disk format of a `TagRegistry`
+ */
+#[derive(serde::Deserialize, serde::Serialize)]
+struct TagSnapshot {
+	fetched_at: chrono::DateTime<chrono::Utc>,
+	tags: Vec<Tag>,
+}
+
+impl TagRegistry {
+	pub fn new(tags: Vec<Tag>, fetched_at: chrono::DateTime<chrono::Utc>) -> Self {
+		let mut registry = Self {
+			tags: vec![],
+			fetched_at,
+			ttl: TAG_LIST_TTL,
+			by_id: HashMap::new(),
+			by_slug: HashMap::new(),
+			by_name: HashMap::new(),
+		};
+		registry.index(tags);
+		registry
+	}
+
+	/**
+	 * Fetch the official tag list from the server
+	 */
+	pub async fn load<T: HttpTransport>(
+		client: &AuthorizedClient<T>,
+	) -> Result<TagRegistry, HampterError> {
+		Ok(Self::new(
+			Tag::get_tag_list(client).await?,
+			chrono::Utc::now(),
+		))
+	}
+
+	/**
+	 * Read a registry written by `save`, it is refreshed like any other once the TTL ran out
+	 */
+	pub fn open(path: impl AsRef<Path>) -> Result<TagRegistry, HampterError> {
+		let snapshot: TagSnapshot = serde_json::from_slice(&std::fs::read(path)?)?;
+		Ok(Self::new(snapshot.tags, snapshot.fetched_at))
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HampterError> {
+		let snapshot = TagSnapshot {
+			fetched_at: self.fetched_at,
+			tags: self.tags.clone(),
+		};
+		Ok(std::fs::write(path, serde_json::to_vec_pretty(&snapshot)?)?)
+	}
+
+	pub fn with_ttl(mut self, ttl: Duration) -> Self {
+		self.ttl = ttl;
+		self
+	}
+
+	pub fn tags(&self) -> &[Tag] {
+		&self.tags
+	}
+
+	pub fn fetched_at(&self) -> chrono::DateTime<chrono::Utc> {
+		self.fetched_at
+	}
+
+	pub fn is_stale(&self) -> bool {
+		match (chrono::Utc::now() - self.fetched_at).to_std() {
+			Ok(age) => age >= self.ttl,
+			Err(_) => false, // fetched in the future, the clock was changed
+		}
+	}
+
+	pub async fn refresh<T: HttpTransport>(
+		&mut self,
+		client: &AuthorizedClient<T>,
+	) -> Result<(), HampterError> {
+		let tags = Tag::get_tag_list(client).await?;
+		self.fetched_at = chrono::Utc::now();
+		self.index(tags);
+		Ok(())
+	}
+
+	/**
+	 * Refresh the tag list once it's older than the TTL, returns whether it was refreshed
+	 */
+	pub async fn refresh_if_stale<T: HttpTransport>(
+		&mut self,
+		client: &AuthorizedClient<T>,
+	) -> Result<bool, HampterError> {
+		if !self.is_stale() {
+			return Ok(false);
+		}
+		self.refresh(client).await?;
+		Ok(true)
+	}
+
+	pub fn by_id(&self, id: u32) -> Option<&Tag> {
+		self.by_id.get(&id).map(|i: &usize| &self.tags[*i])
+	}
+
+	pub fn by_slug(&self, slug: &str) -> Option<&Tag> {
+		self.by_slug
+			.get(&slug.to_lowercase())
+			.map(|i: &usize| &self.tags[*i])
+	}
+
+	/**
+	 * Look up a tag by its display name, ignoring case and surrounding whitespace
+	 */
+	pub fn by_name(&self, name: &str) -> Option<&Tag> {
+		self.by_name
+			.get(&name.trim().to_lowercase())
+			.map(|i: &usize| &self.tags[*i])
+	}
+
+	/**
+	 * Find the tag a user means, trying the id, then the name, then the slug
+	 * "Sci-Fi", "sci-fi" and "sci fi" all resolve to the same tag.
+	 */
+	pub fn resolve(&self, input: &str) -> Option<&Tag> {
+		let input = input.trim();
+		if let Ok(id) = input.parse::<u32>() {
+			return self.by_id(id);
+		}
+		self.by_name(input)
+			.or_else(|| self.by_slug(input))
+			.or_else(|| self.by_slug(&slugify(input)))
+	}
+
+	/**
+	 * Turn user input into the `tag_ids` of `Character::query`, failing on the first unknown tag
+	 */
+	pub fn resolve_ids(&self, inputs: &[&str]) -> Result<Vec<u32>, HampterError> {
+		inputs
+			.iter()
+			.map(|input: &&str| {
+				self.resolve(input)
+					.map(|tag: &Tag| tag.id)
+					.ok_or_else(|| HampterError::InvalidInput(format!("unknown tag `{}`", input)))
+			})
+			.collect()
+	}
+
+	fn index(&mut self, tags: Vec<Tag>) {
+		self.by_id.clear();
+		self.by_slug.clear();
+		self.by_name.clear();
+		for (i, tag) in tags.iter().enumerate() {
+			self.by_id.insert(tag.id, i);
+			self.by_slug.insert(tag.slug.to_lowercase(), i);
+			self.by_name.insert(tag.name.trim().to_lowercase(), i);
+		}
+		self.tags = tags;
+	}
+}
+
+fn slugify(input: &str) -> String {
+	input
+		.to_lowercase()
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|s: &&str| !s.is_empty())
+		.collect::<Vec<&str>>()
+		.join("-")
+}
+
+#[derive(Clone)]
+pub enum Suggestion {
+	Official(Tag), // usable as `tag_ids` in `Character::query`
+	Custom(String),
+}
+
+/**
+ * Suggestions for a search box, asking the server as rarely as possible
+ * Input is debounced, answers are cached per prefix and a newer call to `suggest` cancels the
+ * request of an older one. Official tags matching the input come first and also work for prefixes
+ * too short for the server.
+ */
+pub struct TagAutocomplete {
+	official: Vec<Tag>,
+	debounce: Duration,
+	cache: Mutex<HashMap<String, Vec<String>>>,
+	superseded: tokio::sync::Notify,
+}
+
+impl Default for TagAutocomplete {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl TagAutocomplete {
+	pub fn new() -> Self {
+		Self {
+			official: vec![],
+			debounce: Duration::from_millis(250),
+			cache: Mutex::new(HashMap::new()),
+			superseded: tokio::sync::Notify::new(),
+		}
+	}
+
+	/**
+	 * Official tags to suggest alongside custom tags, e.g. `TagRegistry::tags`
+	 */
+	pub fn with_official_tags(mut self, tags: &[Tag]) -> Self {
+		self.official = tags.to_vec();
+		self
+	}
+
+	pub fn with_debounce(mut self, debounce: Duration) -> Self {
+		self.debounce = debounce;
+		self
+	}
+
+	pub fn clear_cache(&self) {
+		self.cache.lock().unwrap().clear();
+	}
+
+	/**
+	 * Call on every keystroke, resolves to `None` if a later call superseded this one
+	 */
+	pub async fn suggest<T: HttpTransport>(
+		&self,
+		input: &str,
+		client: &AuthorizedClient<T>,
+	) -> Result<Option<Vec<Suggestion>>, HampterError> {
+		self.superseded.notify_waiters();
+		let superseded = self.superseded.notified();
+		tokio::pin!(superseded);
+
+		let prefix = normalize_custom_tag(input);
+		let custom = match self.cached(&prefix) {
+			Some(custom) => custom,
+			None if prefix.chars().count() < SUGGESTION_MIN_PREFIX => vec![],
+			None => {
+				tokio::select! {
+					_ = &mut superseded => return Ok(None),
+					_ = tokio::time::sleep(self.debounce) => {}
+				}
+				// dropping the request future aborts it
+				let custom = tokio::select! {
+					_ = &mut superseded => return Ok(None),
+					custom = Tag::get_tag_suggestion(&prefix, client) => custom?,
+				};
+				self.cache
+					.lock()
+					.unwrap()
+					.insert(prefix.clone(), custom.clone());
+				custom
+			}
+		};
+		Ok(Some(self.merge(&prefix, custom)))
+	}
+
+	/**
+	 * A cached answer for `prefix`, or one filtered from a shorter prefix whose answer was complete
+	 */
+	fn cached(&self, prefix: &str) -> Option<Vec<String>> {
+		let cache = self.cache.lock().unwrap();
+		if let Some(custom) = cache.get(prefix) {
+			return Some(custom.clone());
+		}
+		prefix
+			.char_indices()
+			.map(|(i, _)| &prefix[..i])
+			.filter(|p: &&str| p.chars().count() >= SUGGESTION_MIN_PREFIX)
+			.filter_map(|p: &str| cache.get(p))
+			.find(|custom: &&Vec<String>| custom.len() < SUGGESTION_LIMIT)
+			.map(|custom: &Vec<String>| {
+				custom
+					.iter()
+					.filter(|t: &&String| normalize_custom_tag(t).starts_with(prefix))
+					.cloned()
+					.collect()
+			})
+	}
+
+	fn merge(&self, prefix: &str, custom: Vec<String>) -> Vec<Suggestion> {
+		if prefix.is_empty() {
+			return vec![];
+		}
+		let official: Vec<&Tag> = self
+			.official
+			.iter()
+			.filter(|t: &&Tag| {
+				t.name.to_lowercase().starts_with(prefix)
+					|| t.slug.to_lowercase().starts_with(prefix)
+			})
+			.collect();
+		let custom = custom.into_iter().filter(|c: &String| {
+			!official
+				.iter()
+				.any(|t: &&Tag| t.name.to_lowercase() == normalize_custom_tag(c))
+		});
+		official
+			.iter()
+			.map(|t: &&Tag| Suggestion::Official((*t).clone()))
+			.chain(custom.map(Suggestion::Custom))
+			.collect()
+	}
+}
+
+fn normalize_custom_tag(tag: &str) -> String {
+	tag.trim().to_lowercase()
+}
+
+/**
+ * Lowercase every tag and drop duplicates, keeping the original order
+ */
+pub(crate) fn normalize_custom_tags(tags: Vec<String>) -> Vec<String> {
+	let mut normalized: Vec<String> = vec![];
+	for tag in tags {
+		let tag = normalize_custom_tag(&tag);
+		if !normalized.contains(&tag) {
+			normalized.push(tag);
+		}
+	}
+	normalized
 }